@@ -0,0 +1,80 @@
+// Counts the allocations it takes to call a function and to read a string,
+// for bigger and bigger functions and strings. Bodies and strings are
+// shared rather than copied, so neither should grow with the size.
+// Run with `cargo bench --bench allocations`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use kaffee::{interpreter, Backend};
+
+struct Counting;
+
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc (&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc (&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc (&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const ITERATIONS: usize = 1000;
+const SIZES: &[usize] = &[10, 100, 1000];
+
+// Allocations and bytes per loop iteration, less what the loop costs by itself
+fn per_iteration (setup: &str, body: &str, backend: Backend) -> (f64, f64) {
+    let measure = |body: &str| {
+        let mut interp = interpreter::new(String::new()).unwrap();
+        interp.backend = backend;
+        // Declaring everything first keeps the setup out of the count
+        interp.eval(setup.to_string()).unwrap();
+        let code = format!("for let i = 0 i < {} i++ {{ {} }}", ITERATIONS, body);
+        let ast = interp.parse(code).unwrap();
+
+        let (allocs, bytes) = (ALLOCS.load(Ordering::Relaxed), BYTES.load(Ordering::Relaxed));
+        for node in &ast {
+            interp.eval_statement(node).unwrap();
+        }
+        (ALLOCS.load(Ordering::Relaxed) - allocs, BYTES.load(Ordering::Relaxed) - bytes)
+    };
+
+    let (allocs, bytes) = measure(body);
+    let (base_allocs, base_bytes) = measure("");
+    let per = |n: usize, base: usize| n.saturating_sub(base) as f64 / ITERATIONS as f64;
+    (per(allocs, base_allocs), per(bytes, base_bytes))
+}
+
+fn main () {
+    println!("{:<22} {:>18} {:>18}", "", "tree walker", "bytecode");
+    for size in SIZES {
+        let statements = "let unused = 1 + 2\n".repeat(*size);
+        let setup = format!("fn f (x) {{ if x >= 0 return x\n {} }}", statements);
+        report(&format!("call, {} statements", size), &setup, "f(i)");
+    }
+    for size in SIZES {
+        let setup = format!("let s = \"{}\"", "a".repeat(size * 100));
+        report(&format!("read, {} chars", size * 100), &setup, "let t = s");
+    }
+}
+
+fn report (name: &str, setup: &str, body: &str) {
+    let (walked, walked_bytes) = per_iteration(setup, body, Backend::TreeWalker);
+    let (compiled, compiled_bytes) = per_iteration(setup, body, Backend::Bytecode);
+    println!("{:<22} {:>6.1} ({:>6.0} B) {:>6.1} ({:>6.0} B)",
+        name, walked, walked_bytes, compiled, compiled_bytes);
+}
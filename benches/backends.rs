@@ -0,0 +1,53 @@
+// The same scripts on the tree walker and the bytecode VM.
+// Run with `cargo bench --bench backends`.
+
+use std::time::{Duration, Instant};
+use kaffee::{interpreter, Backend};
+
+const RUNS: u32 = 5;
+
+const SCRIPTS: &[(&str, &str)] = &[
+    ("loops", "
+        let total = 0
+        for let i = 0 i < 100000 i++ {
+            if i % 3 != 0 total += i * 2
+        }
+    "),
+    ("recursion", "
+        fn fib (n) {
+            if n < 2 return n
+            return fib(n - 1) + fib(n - 2)
+        }
+        fib(18)
+    "),
+    ("objects", "
+        const point = { x: 0, y: 0, z: 0 }
+        for let i = 0 i < 30000 i++ {
+            point.x = point.y + 1
+            point.y = point.z + point.x
+            point[\"z\"]++
+        }
+    ")
+];
+
+fn time_script (code: &str, backend: Backend) -> Duration {
+    let mut best = Duration::from_secs(u64::MAX);
+    for _ in 0..RUNS {
+        let mut interp = interpreter::new(code.to_string()).unwrap();
+        interp.backend = backend;
+        let start = Instant::now();
+        interp.run().unwrap();
+        best = best.min(start.elapsed());
+    }
+    best
+}
+
+fn main () {
+    println!("{:<10} {:>12} {:>12} {:>8}", "", "tree walker", "bytecode", "speedup");
+    for (name, code) in SCRIPTS {
+        let walked = time_script(code, Backend::TreeWalker);
+        let compiled = time_script(code, Backend::Bytecode);
+        let speedup = walked.as_secs_f64() / compiled.as_secs_f64();
+        println!("{:<10} {:>12.2?} {:>12.2?} {:>7.2}x", name, walked, compiled, speedup);
+    }
+}
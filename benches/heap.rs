@@ -0,0 +1,64 @@
+// Allocation-heavy scripts, timed end to end.
+// Run with `cargo bench --bench heap`.
+
+use std::time::{Duration, Instant};
+use kaffee::interpreter;
+
+const RUNS: u32 = 5;
+
+const SCRIPTS: &[(&str, &str)] = &[
+    ("objects", "
+        let i = 0
+        while i < 20000 {
+            let o = { a: i, b: { c: [i, i, i] } }
+            i++
+        }
+    "),
+    ("arrays", "
+        let list = []
+        let i = 0
+        while i < 300 {
+            list = append(list, [i, i + 1])
+            i++
+        }
+    "),
+    ("closures", "
+        fn counter() {
+            let n = 0
+            return fn() { n++ return n }
+        }
+        let i = 0
+        while i < 5000 {
+            let c = counter()
+            c()
+            c()
+            i++
+        }
+    "),
+    ("variables", "
+        let total = 0
+        let i = 0
+        while i < 100000 {
+            total = total + i * 2
+            i++
+        }
+    ")
+];
+
+fn time_script (code: &str) -> Duration {
+    let mut best = Duration::from_secs(u64::MAX);
+    for _ in 0..RUNS {
+        let mut interp = interpreter::new(code.to_string()).unwrap();
+        let start = Instant::now();
+        interp.run().unwrap();
+        best = best.min(start.elapsed());
+    }
+    best
+}
+
+fn main () {
+    for (name, code) in SCRIPTS {
+        let best = time_script(code);
+        println!("{:<10} {:>10.2?} (best of {})", name, best, RUNS);
+    }
+}
@@ -0,0 +1,44 @@
+// Property access on objects of different sizes. Lookups should cost
+// about the same however many keys an object has.
+// Run with `cargo bench --bench objects`.
+
+use std::time::{Duration, Instant};
+use kaffee::interpreter;
+
+const RUNS: u32 = 5;
+const SIZES: &[usize] = &[10, 100, 1000, 10000];
+const LOOKUPS: usize = 20000;
+
+// Builds the object, then times reading from it
+fn time_lookups (size: usize) -> (Duration, Duration) {
+    let build = format!("
+        let o = {{}}
+        for let i = 0 i < {} i++ o[i] = i
+    ", size);
+    let lookups = format!("
+        let total = 0
+        for let i = 0 i < {} i++ total += o[i % {}]
+    ", LOOKUPS, size);
+
+    let mut best_build = Duration::from_secs(u64::MAX);
+    let mut best_lookups = Duration::from_secs(u64::MAX);
+    for _ in 0..RUNS {
+        let mut interp = interpreter::new(build.clone()).unwrap();
+        let start = Instant::now();
+        interp.run().unwrap();
+        best_build = best_build.min(start.elapsed());
+
+        let start = Instant::now();
+        interp.eval(lookups.clone()).unwrap();
+        best_lookups = best_lookups.min(start.elapsed());
+    }
+    (best_build, best_lookups)
+}
+
+fn main () {
+    println!("{:>6} keys {:>12} {:>12} {:>12}", "", "build", "lookups", "per lookup");
+    for size in SIZES {
+        let (build, lookups) = time_lookups(*size);
+        println!("{:>6} keys {:>12.2?} {:>12.2?} {:>12.2?}", size, build, lookups, lookups / LOOKUPS as u32);
+    }
+}
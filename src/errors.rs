@@ -0,0 +1,189 @@
+use crate::parsing::span::Span;
+use crate::interpretting::interpreter_utils::KaffeeValue;
+use std::fmt;
+
+// Roughly follows the JS error names, so scripts
+// see familiar kinds
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ErrorKind {
+    Syntax,
+    // Unresolved identifiers
+    Reference,
+    // Missing object keys, out of range array indexes
+    Key,
+    // Operators and calls on values that don't support them
+    Type,
+    // Calling a function with the wrong number of arguments
+    Argument,
+    ConstAssignment,
+    // Declaring the same name twice in a scope
+    Redeclaration,
+    // Dividing by zero
+    Arithmetic,
+    Io,
+    // The script went over the heap limits it was given
+    OutOfMemory,
+    // Recursing deeper than the interpreter allows
+    StackOverflow,
+    // Going over the step budget or deadline. Scripts can't catch these.
+    ExecutionLimit,
+    // A value thrown by the program with `throw`
+    Thrown
+}
+
+impl ErrorKind {
+    pub fn name (&self) -> &'static str {
+        match self {
+            ErrorKind::Syntax => "SyntaxError",
+            ErrorKind::Reference => "ReferenceError",
+            ErrorKind::Key => "KeyError",
+            ErrorKind::Type => "TypeError",
+            ErrorKind::Argument => "ArgumentError",
+            ErrorKind::ConstAssignment => "ConstAssignmentError",
+            ErrorKind::Redeclaration => "RedeclarationError",
+            ErrorKind::Arithmetic => "ArithmeticError",
+            ErrorKind::Io => "IOError",
+            ErrorKind::OutOfMemory => "OutOfMemoryError",
+            ErrorKind::StackOverflow => "StackOverflowError",
+            ErrorKind::ExecutionLimit => "ExecutionLimitError",
+            ErrorKind::Thrown => "Error"
+        }
+    }
+}
+
+// A function call that was running when an error happened
+#[derive(Clone, PartialEq, Debug)]
+pub struct StackFrame {
+    // The function's name, or <anonymous>
+    pub name: String,
+    // Where it was called from
+    pub span: Span
+}
+
+impl fmt::Display for StackFrame {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at {} ({}:{})", self.name, self.span.start.line, self.span.start.col)
+    }
+}
+
+const MAX_TRACE_LINES: usize = 20;
+
+#[derive(Clone, Debug)]
+pub struct KaffeeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    // Where in the code it went wrong
+    pub span: Option<Span>,
+    // What was thrown, for Thrown errors
+    pub value: Option<Box<KaffeeValue>>,
+    // The calls it happened inside, innermost first
+    pub trace: Vec<StackFrame>
+}
+
+impl KaffeeError {
+    pub fn new (kind: ErrorKind, message: String) -> KaffeeError {
+        KaffeeError { kind, message, span: None, value: None, trace: vec![] }
+    }
+
+    pub fn thrown (value: KaffeeValue, message: String) -> KaffeeError {
+        KaffeeError {
+            kind: ErrorKind::Thrown,
+            message,
+            span: None,
+            value: Some(Box::new(value)),
+            trace: vec![]
+        }
+    }
+
+    // Errors are often made without knowing where they are
+    // (eg. in Variables), so the first AST node they pass
+    // through on the way up gives them a location
+    pub fn with_span (mut self, span: Span) -> KaffeeError {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+
+    // Whether scripts get a say in it, with catch and finally blocks.
+    // Not even finally gets to run once time's up.
+    pub fn catchable (&self) -> bool {
+        self.kind != ErrorKind::ExecutionLimit
+    }
+
+    // The stack trace as "at name (line:col)" lines. Really deep
+    // traces (eg. runaway recursion) only show the innermost calls.
+    pub fn render_trace (&self) -> String {
+        let mut lines: Vec<String> = self.trace.iter()
+            .take(MAX_TRACE_LINES)
+            .map(|frame| format!("  {}", frame))
+            .collect();
+        if self.trace.len() > MAX_TRACE_LINES {
+            lines.push(format!("  ... and {} more", self.trace.len() - MAX_TRACE_LINES));
+        }
+        lines.join("\n")
+    }
+
+    // Formats the error with the line of code it happened on,
+    // a caret underline of the exact part and the stack trace.
+    // `source` should be the code that the span points into.
+    pub fn render (&self, source: &str) -> String {
+        let mut out = self.render_location(source);
+        if !self.trace.is_empty() {
+            out = format!("{}\n{}", out, self.render_trace());
+        }
+        out
+    }
+
+    fn render_location (&self, source: &str) -> String {
+        let header = format!("{}: {}", self.kind.name(), self.message);
+        let span = match self.span {
+            Some(s) => s,
+            None => return header
+        };
+
+        let line = match source.lines().nth(span.start.line - 1) {
+            Some(l) => l,
+            None => return format!("{}\n --> {}:{}", header, span.start.line, span.start.col)
+        };
+        let line_chars: Vec<char> = line.chars().collect();
+
+        let gutter = span.start.line.to_string();
+        let pad = " ".repeat(gutter.len());
+
+        // Keep tabs so that the carets line up with the code above
+        let indent: String = line_chars.iter()
+            .take(span.start.col - 1)
+            .map(|c| if *c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        // Spans over several lines are underlined to the end of the first
+        let end_col = if span.end.line == span.start.line {
+            span.end.col
+        } else {
+            line_chars.len() + 1
+        };
+        let carets = "^".repeat(end_col.saturating_sub(span.start.col).max(1));
+
+        format!("{}\n{}--> {}:{}\n{} |\n{} | {}\n{} | {}{}",
+            header,
+            pad, span.start.line, span.start.col,
+            pad,
+            gutter, line,
+            pad, indent, carets)
+    }
+}
+
+impl fmt::Display for KaffeeError {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.kind.name(), self.message)?;
+        if let Some(span) = self.span {
+            write!(f, " at {}:{}", span.start.line, span.start.col)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for KaffeeError {}
+
+pub type KaffeeResult<T> = Result<T, KaffeeError>;
@@ -0,0 +1,187 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::{Index, IndexMut};
+
+// A Vec-backed store where freed slots get reused.
+// Each slot has a generation that goes up when it's freed, so a handle
+// to something that's been freed can't see whatever took its place.
+
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    kind: PhantomData<fn() -> T>
+}
+
+impl<T> Handle<T> {
+    // Which slot it points at, for things like mark bits
+    pub fn index (&self) -> usize {
+        self.index as usize
+    }
+
+    pub fn generation (&self) -> u32 {
+        self.generation
+    }
+}
+
+// Derives would want T: Clone etc., which handles don't need
+impl<T> Clone for Handle<T> {
+    fn clone (&self) -> Self { *self }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq (&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher> (&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl<T> fmt::Display for Handle<T> {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}v{}", self.index, self.generation)
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>
+}
+
+pub struct Arena<T> {
+    slots: Vec<Slot<T>>,
+    // Empty slots, reused before the Vec grows
+    free: Vec<u32>,
+    len: usize
+}
+
+impl<T> Arena<T> {
+    pub fn insert (&mut self, value: T) -> Handle<T> {
+        self.len += 1;
+
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.value = Some(value);
+            return Handle { index, generation: slot.generation, kind: PhantomData }
+        }
+
+        self.slots.push(Slot { generation: 0, value: Some(value) });
+        Handle {
+            index: (self.slots.len() - 1) as u32,
+            generation: 0,
+            kind: PhantomData
+        }
+    }
+
+    pub fn get (&self, handle: Handle<T>) -> Option<&T> {
+        match self.slots.get(handle.index()) {
+            Some(slot) if slot.generation == handle.generation => slot.value.as_ref(),
+            _ => None
+        }
+    }
+
+    pub fn get_mut (&mut self, handle: Handle<T>) -> Option<&mut T> {
+        match self.slots.get_mut(handle.index()) {
+            Some(slot) if slot.generation == handle.generation => slot.value.as_mut(),
+            _ => None
+        }
+    }
+
+    pub fn contains (&self, handle: Handle<T>) -> bool {
+        self.get(handle).is_some()
+    }
+
+    pub fn remove (&mut self, handle: Handle<T>) -> Option<T> {
+        if !self.contains(handle) { return None }
+        self.free_slot(handle.index)
+    }
+
+    // Keeps only the values that f says to keep
+    pub fn retain (&mut self, mut f: impl FnMut(Handle<T>, &T) -> bool) {
+        for index in 0..self.slots.len() {
+            let slot = &self.slots[index];
+            let keep = match &slot.value {
+                Some(value) => f(Handle { index: index as u32, generation: slot.generation, kind: PhantomData }, value),
+                None => continue
+            };
+            if !keep {
+                self.free_slot(index as u32);
+            }
+        }
+    }
+
+    fn free_slot (&mut self, index: u32) -> Option<T> {
+        let slot = &mut self.slots[index as usize];
+        let value = slot.value.take();
+        // Old handles to this slot are stale from now on
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(index);
+        self.len -= 1;
+        value
+    }
+
+    // How many values are alive
+    pub fn len (&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.len == 0
+    }
+
+    // How many slots there are, alive or not. Handle indexes are below this.
+    pub fn slot_count (&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn iter (&self) -> impl Iterator<Item = (Handle<T>, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.value.as_ref().map(|value| {
+                (Handle { index: index as u32, generation: slot.generation, kind: PhantomData }, value)
+            })
+        })
+    }
+}
+
+impl<T> Index<Handle<T>> for Arena<T> {
+    type Output = T;
+
+    fn index (&self, handle: Handle<T>) -> &T {
+        match self.get(handle) {
+            Some(value) => value,
+            None => panic!("Use of freed heap value {}", handle)
+        }
+    }
+}
+
+impl<T> IndexMut<Handle<T>> for Arena<T> {
+    fn index_mut (&mut self, handle: Handle<T>) -> &mut T {
+        match self.get_mut(handle) {
+            Some(value) => value,
+            None => panic!("Use of freed heap value {}", handle)
+        }
+    }
+}
+
+pub fn new<T> () -> Arena<T> {
+    Arena {
+        slots: vec![],
+        free: vec![],
+        len: 0
+    }
+}
@@ -0,0 +1,132 @@
+use crate::parsing::ast_utils::{ASTNode, IdentifierProperties};
+use crate::parsing::span::Span;
+use crate::std_lib::operators::BinaryOp;
+use std::rc::Rc;
+
+// The instructions the VM runs. Each one is small enough to copy, anything
+// bigger (strings, function bodies etc.) lives in the chunk's tables and
+// the op just has its index.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Op {
+    // Push a value
+    Number(f64),
+    String(u32),
+    Boolean(bool),
+    Null,
+    // A closure over the current scopes, from chunk.functions
+    Function(u32),
+    // Pops this many items into a new array
+    Array(u32),
+    // Pops a value for each of the keys in chunk.objects
+    Object(u32),
+
+    // Variables, from chunk.variables
+    Get(u32),
+    // Pops the value
+    Set(u32),
+    Declare { variable: u32, constant: bool },
+    // ++ and --
+    Update { variable: u32, increment: bool, prefix: bool },
+
+    // Pops the key and the object. Missing keys are
+    // reported at the key's span, from chunk.part_spans.
+    GetProperty(u32),
+    // Pops the key, the object then the value
+    SetProperty,
+    UpdateProperty { increment: bool, prefix: bool },
+
+    Binary(BinaryOp),
+    Not,
+    Negate,
+    Plus,
+    Pop,
+
+    // Jumps are to an index in chunk.code
+    Jump(u32),
+    // Pops the condition
+    JumpIfFalse(u32),
+    // && and ||. If the left side decides it, it's left on the stack
+    // and the right side is jumped over, otherwise it's popped.
+    And(u32),
+    Or(u32),
+    // The end of a loop iteration, where the GC can run
+    Loop(u32),
+
+    EnterScope,
+    LeaveScope,
+    // Between the statements of a block, where the GC can run
+    Safepoint,
+    // Counts a step towards the step budget. They go wherever the tree
+    // walker counts one (each statement, call and loop iteration), so
+    // both backends stop at the same place.
+    Tick,
+
+    // Pops the arguments, then the callee. Uncallable values are
+    // reported at the callee's span, from chunk.part_spans.
+    Call { args: u32, callee: u32 },
+    // A call that's the last thing its function does, followed by a
+    // Return. When it can, it takes over the caller's frame instead.
+    TailCall { args: u32, callee: u32 },
+    // Pops the return value. Runs any finally blocks on the way out.
+    Return,
+    Throw,
+    // Raises a syntax error, with the message in chunk.strings. The tree
+    // walker only complains about some mistakes once it reaches them,
+    // so the compiler leaves these in their place.
+    Fail(u32),
+
+    // Starts a try block, from chunk.tries
+    Try(u32),
+    // The try block finished without an error
+    EndTry,
+    // The end of a try and catch, running into their finally block
+    Finally,
+    // Carries on with whatever was happening before the finally block
+    EndFinally,
+    // break and continue, from chunk.leaves
+    Leave(u32)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TryInfo {
+    pub catch: Option<usize>,
+    pub finally: Option<usize>
+}
+
+// Where a break or continue goes, and what it leaves behind on the way
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LeaveInfo {
+    pub target: usize,
+    // How many try and finally blocks it's still inside
+    pub handlers: usize,
+    // How many scopes in from the start of the chunk it ends up
+    pub scopes: usize
+}
+
+// A compiled function body, or a top-level statement
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    // Where each op came from, for errors
+    pub spans: Vec<Span>,
+    pub strings: Vec<Rc<str>>,
+    // Variables with where the resolver put them
+    pub variables: Vec<IdentifierProperties>,
+    pub functions: Vec<Rc<FunctionProto>>,
+    // The keys of each object literal
+    pub objects: Vec<Vec<Rc<str>>>,
+    // Where parts of some ops' nodes are, for errors about just that part
+    pub part_spans: Vec<Span>,
+    pub tries: Vec<TryInfo>,
+    pub leaves: Vec<LeaveInfo>
+}
+
+// What a closure is made from. The AST is kept too, so the functions
+// the VM makes can still be run by the tree walker.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FunctionProto {
+    pub name: Rc<str>,
+    pub args: Rc<Vec<String>>,
+    pub body: Rc<Vec<ASTNode>>,
+    pub chunk: Rc<Chunk>
+}
@@ -0,0 +1,442 @@
+use crate::parsing::ast_utils::*;
+use crate::parsing::span::Span;
+use crate::interpretting::bytecode::*;
+use crate::std_lib::operators::BinaryOp;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Turns the AST into bytecode for the VM, one chunk per top-level
+// statement and one per function. Compiling can't fail - anything the
+// tree walker would only complain about when it got there becomes a
+// Fail op in the same place.
+
+struct Compiler {
+    chunk: Chunk,
+    // Where each string already is in chunk.strings
+    string_indexes: HashMap<String, u32>,
+    // How many scopes and try/finally blocks the code being compiled is
+    // inside, counted from the start of the chunk
+    scopes: usize,
+    handlers: usize,
+    loops: Vec<LoopState>
+}
+
+struct LoopState {
+    scopes: usize,
+    handlers: usize,
+    // chunk.leaves entries that need pointing at the end of
+    // the loop, or the end of the iteration
+    breaks: Vec<usize>,
+    continues: Vec<usize>
+}
+
+// A top-level statement. If it's an expression, the chunk
+// returns its value, otherwise null.
+pub fn compile_statement (node: &ASTNode) -> Chunk {
+    let mut comp = new_compiler();
+    if node.is_expression() {
+        // Counted as a step, like any other statement
+        comp.emit(Op::Tick, node.span);
+        comp.expression(node);
+    } else {
+        comp.statement(node);
+        comp.emit(Op::Null, node.span);
+    }
+    comp.emit(Op::Return, node.span);
+    comp.chunk
+}
+
+// A function's body, which gets a block scope of its own. The span is
+// for the ops that aren't from the body itself.
+pub fn compile_function (body: &[ASTNode], span: Span) -> Chunk {
+    let mut comp = new_compiler();
+    comp.emit(Op::EnterScope, span);
+    comp.scopes += 1;
+    comp.block(body);
+    comp.emit(Op::Null, span);
+    comp.emit(Op::Return, span);
+    comp.chunk
+}
+
+fn new_compiler () -> Compiler {
+    Compiler {
+        chunk: Chunk::default(),
+        string_indexes: HashMap::new(),
+        scopes: 0,
+        handlers: 0,
+        loops: vec![]
+    }
+}
+
+impl Compiler {
+    fn emit (&mut self, op: Op, span: Span) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.spans.push(span);
+        self.chunk.code.len() - 1
+    }
+
+    // Where the next op will go
+    fn here (&self) -> u32 {
+        self.chunk.code.len() as u32
+    }
+
+    // Points a jump emitted earlier at the next op
+    fn patch (&mut self, at: usize) {
+        let target = self.here();
+        self.chunk.code[at] = match self.chunk.code[at] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            Op::And(_) => Op::And(target),
+            Op::Or(_) => Op::Or(target),
+            _ => unreachable!()
+        }
+    }
+
+    fn string (&mut self, st: &str) -> u32 {
+        if let Some(idx) = self.string_indexes.get(st) {
+            return *idx
+        }
+
+        let idx = self.chunk.strings.len() as u32;
+        self.chunk.strings.push(st.into());
+        self.string_indexes.insert(st.to_string(), idx);
+        idx
+    }
+
+    fn variable (&mut self, id: &IdentifierProperties) -> u32 {
+        self.chunk.variables.push(id.clone());
+        self.chunk.variables.len() as u32 - 1
+    }
+
+    fn part_span (&mut self, span: Span) -> u32 {
+        self.chunk.part_spans.push(span);
+        self.chunk.part_spans.len() as u32 - 1
+    }
+
+    fn fail (&mut self, message: &str, span: Span) {
+        let idx = self.string(message);
+        self.emit(Op::Fail(idx), span);
+    }
+
+    fn statement (&mut self, node: &ASTNode) {
+        let span = node.span;
+        self.emit(Op::Tick, span);
+        match &node.kind {
+            NodeKind::BlockStatement(bs) => {
+                self.emit(Op::EnterScope, span);
+                self.scopes += 1;
+                self.block(bs);
+                self.scopes -= 1;
+                self.emit(Op::LeaveScope, span);
+            },
+            NodeKind::Declaration(dcl) => {
+                if let NodeKind::Identifier(id) = &dcl.assignment.left.kind {
+                    self.expression(dcl.assignment.right.as_ref());
+                    let variable = self.variable(id);
+                    self.emit(Op::Declare { variable, constant: dcl.constant }, span);
+                } else {
+                    self.fail("Left side of a declaration isn't an identifier", span)
+                }
+            },
+            NodeKind::Assignment(asn) => self.assignment(asn, span),
+            NodeKind::FunctionCall(_) | NodeKind::UpdateNode(_) => {
+                self.expression(node);
+                self.emit(Op::Pop, span);
+            },
+            NodeKind::FunctionDefinition(fd) => {
+                self.function(fd, span);
+                let variable = self.variable(&IdentifierProperties {
+                    name: fd.name.to_string(),
+                    binding: fd.slot.map(|slot| Binding { depth: 0, slot })
+                });
+                self.emit(Op::Declare { variable, constant: false }, span);
+            },
+            NodeKind::IfStatement(ifs) => {
+                self.expression(ifs.check_exp.as_ref());
+                let to_else = self.emit(Op::JumpIfFalse(0), span);
+                self.statement(ifs.body.as_ref());
+
+                if let Some(en) = &ifs.else_exp {
+                    let to_end = self.emit(Op::Jump(0), span);
+                    self.patch(to_else);
+                    self.statement(en.as_ref());
+                    self.patch(to_end);
+                } else {
+                    self.patch(to_else);
+                }
+            },
+            NodeKind::ReturnStatement(rs) => {
+                self.expression(rs.as_ref());
+                self.emit(Op::Return, span);
+            },
+            NodeKind::ContinueStatement => self.leave(false, span),
+            NodeKind::BreakStatement => self.leave(true, span),
+            NodeKind::WhileLoop(wp) => self.while_loop(wp, span),
+            NodeKind::TryStatement(tp) => self.try_statement(tp, span),
+            NodeKind::ThrowStatement(ts) => {
+                self.expression(ts.as_ref());
+                self.emit(Op::Throw, span);
+            },
+            _ => self.fail("This expression can't be used as a statement", span)
+        }
+    }
+
+    fn block (&mut self, bs: &[ASTNode]) {
+        for n in bs {
+            self.statement(n);
+            self.emit(Op::Safepoint, n.span);
+        }
+    }
+
+    fn assignment (&mut self, bin: &BinaryProperties, span: Span) {
+        // The value comes first, like in the tree walker
+        self.expression(bin.right.as_ref());
+
+        let target = bin.left.as_ref();
+        match &target.kind {
+            NodeKind::Identifier(id) => {
+                let variable = self.variable(id);
+                self.emit(Op::Set(variable), target.span);
+            },
+            NodeKind::PropertyAccess(pa) => {
+                self.expression(pa.object.as_ref());
+                self.expression(pa.property.as_ref());
+                self.emit(Op::SetProperty, target.span);
+            },
+            _ => {
+                self.emit(Op::Pop, span);
+                self.fail("Can't assign to this type", target.span)
+            }
+        }
+    }
+
+    fn while_loop (&mut self, wp: &WhileProperties, span: Span) {
+        let start = self.here();
+        self.expression(wp.check.as_ref());
+        let to_end = self.emit(Op::JumpIfFalse(0), span);
+        self.emit(Op::Tick, span);
+
+        self.loops.push(LoopState {
+            scopes: self.scopes,
+            handlers: self.handlers,
+            breaks: vec![],
+            continues: vec![]
+        });
+        self.statement(wp.body.as_ref());
+        let state = self.loops.pop().unwrap();
+
+        // continue still has to go through the end of the iteration
+        for leave in state.continues {
+            self.chunk.leaves[leave].target = self.here() as usize;
+        }
+        self.emit(Op::Loop(start), wp.body.span);
+
+        self.patch(to_end);
+        for leave in state.breaks {
+            self.chunk.leaves[leave].target = self.here() as usize;
+        }
+    }
+
+    // break and continue
+    fn leave (&mut self, is_break: bool, span: Span) {
+        let (scopes, handlers) = match self.loops.last() {
+            Some(state) => (state.scopes, state.handlers),
+            None => {
+                // Outside of a loop they finish the function (returning
+                // null) or top-level statement, like in the tree walker
+                self.emit(Op::Null, span);
+                self.emit(Op::Return, span);
+                return
+            }
+        };
+
+        let leave = self.chunk.leaves.len();
+        self.chunk.leaves.push(LeaveInfo { target: 0, handlers, scopes });
+        self.emit(Op::Leave(leave as u32), span);
+
+        let state = self.loops.last_mut().unwrap();
+        if is_break {
+            state.breaks.push(leave)
+        } else {
+            state.continues.push(leave)
+        }
+    }
+
+    //     Try           (catch and finally point at their code)
+    //     <body>
+    //     EndTry
+    //     Jump end
+    //   catch:          (the caught value is on the stack)
+    //     EnterScope, Declare or Pop
+    //     <catch body>
+    //     LeaveScope
+    //     EndTry        (only if there's a finally, to stop catching)
+    //   end:
+    //     Finally
+    //   finally:        (whatever's waiting to happen is on the stack)
+    //     <finally body>
+    //     EndFinally
+    fn try_statement (&mut self, tp: &TryProperties, span: Span) {
+        let info = self.chunk.tries.len();
+        self.chunk.tries.push(TryInfo { catch: None, finally: None });
+        self.emit(Op::Try(info as u32), span);
+
+        self.handlers += 1;
+        self.statement(tp.body.as_ref());
+        self.handlers -= 1;
+        self.emit(Op::EndTry, span);
+
+        if let Some(catch_body) = &tp.catch_body {
+            let to_end = self.emit(Op::Jump(0), span);
+            self.chunk.tries[info].catch = Some(self.here() as usize);
+
+            self.emit(Op::EnterScope, span);
+            self.scopes += 1;
+            match &tp.catch_name {
+                Some(name) => {
+                    let variable = self.variable(&IdentifierProperties {
+                        name: name.clone(),
+                        binding: None
+                    });
+                    self.emit(Op::Declare { variable, constant: false }, span);
+                },
+                None => { self.emit(Op::Pop, span); }
+            }
+
+            // The finally block still runs if the catch block fails
+            if tp.finally_body.is_some() { self.handlers += 1 }
+            self.statement(catch_body.as_ref());
+            self.scopes -= 1;
+            self.emit(Op::LeaveScope, span);
+            if tp.finally_body.is_some() {
+                self.handlers -= 1;
+                self.emit(Op::EndTry, span);
+            }
+
+            self.patch(to_end);
+        }
+
+        if let Some(finally_body) = &tp.finally_body {
+            self.emit(Op::Finally, span);
+            self.chunk.tries[info].finally = Some(self.here() as usize);
+
+            self.handlers += 1;
+            self.statement(finally_body.as_ref());
+            self.handlers -= 1;
+            self.emit(Op::EndFinally, span);
+        }
+    }
+
+    fn function (&mut self, fd: &FunctionDefinitionProperties, span: Span) {
+        let proto = FunctionProto {
+            name: fd.name.clone(),
+            args: fd.args.clone(),
+            body: fd.body.clone(),
+            chunk: Rc::new(compile_function(&fd.body, span))
+        };
+        let idx = self.chunk.functions.len() as u32;
+        self.chunk.functions.push(Rc::new(proto));
+        self.emit(Op::Function(idx), span);
+    }
+
+    fn expression (&mut self, node: &ASTNode) {
+        let span = node.span;
+        match &node.kind {
+            NodeKind::String(st) => {
+                let idx = self.string(st);
+                self.emit(Op::String(idx), span);
+            },
+            NodeKind::Number(n) => { self.emit(Op::Number(*n), span); },
+            NodeKind::Boolean(bl) => { self.emit(Op::Boolean(*bl), span); },
+            NodeKind::Null => { self.emit(Op::Null, span); },
+            NodeKind::Identifier(id) => {
+                let variable = self.variable(id);
+                self.emit(Op::Get(variable), span);
+            },
+            NodeKind::BinaryNode(bn) => self.binary(bn, span),
+            NodeKind::UnaryNode(un) => {
+                self.expression(un.operand.as_ref());
+                match &un.operator[..] {
+                    "!" => { self.emit(Op::Not, span); },
+                    "-" => { self.emit(Op::Negate, span); },
+                    "+" => { self.emit(Op::Plus, span); },
+                    _ => self.fail(&format!("Invalid operand type for unary operator \"{}\"", un.operator), span)
+                }
+            },
+            NodeKind::UpdateNode(up) => {
+                let increment = up.operator == "++";
+                let target = up.target.as_ref();
+                match &target.kind {
+                    NodeKind::Identifier(id) => {
+                        let variable = self.variable(id);
+                        self.emit(Op::Update { variable, increment, prefix: up.prefix }, span);
+                    },
+                    NodeKind::PropertyAccess(pa) => {
+                        self.expression(pa.object.as_ref());
+                        self.expression(pa.property.as_ref());
+                        self.emit(Op::UpdateProperty { increment, prefix: up.prefix }, span);
+                    },
+                    _ => self.fail("Can't assign to this type", target.span)
+                }
+            },
+            NodeKind::ObjectLiteral(ov) => {
+                for val in &ov.values {
+                    self.expression(val);
+                }
+                let idx = self.chunk.objects.len() as u32;
+                self.chunk.objects.push(ov.keys.clone());
+                self.emit(Op::Object(idx), span);
+            },
+            NodeKind::PropertyAccess(pa) => {
+                self.expression(pa.object.as_ref());
+                self.expression(pa.property.as_ref());
+                let key = self.part_span(pa.property.span);
+                self.emit(Op::GetProperty(key), span);
+            },
+            NodeKind::FunctionDefinition(fd) => self.function(fd, span),
+            NodeKind::FunctionCall(cp) => {
+                self.emit(Op::Tick, span);
+                self.expression(cp.callee.as_ref());
+                for arg in &cp.args {
+                    self.expression(arg);
+                }
+                let callee = self.part_span(cp.callee.span);
+                let args = cp.args.len() as u32;
+                if cp.tail {
+                    self.emit(Op::TailCall { args, callee }, span);
+                } else {
+                    self.emit(Op::Call { args, callee }, span);
+                }
+            },
+            NodeKind::ArrayLiteral(items) => {
+                for item in items {
+                    self.expression(item);
+                }
+                self.emit(Op::Array(items.len() as u32), span);
+            },
+            _ => self.fail("Expected an expression, but found a statement", span)
+        }
+    }
+
+    fn binary (&mut self, bn: &BinaryProperties, span: Span) {
+        self.expression(bn.left.as_ref());
+
+        let short_circuit = match &bn.operator[..] {
+            "&&" => Some(Op::And(0)),
+            "||" => Some(Op::Or(0)),
+            _ => None
+        };
+        if let Some(op) = short_circuit {
+            let to_end = self.emit(op, span);
+            self.expression(bn.right.as_ref());
+            self.patch(to_end);
+            return
+        }
+
+        self.expression(bn.right.as_ref());
+        match BinaryOp::from_operator(&bn.operator) {
+            Some(op) => { self.emit(Op::Binary(op), span); },
+            None => self.fail(&format!("Unknown binary operator \"{}\"", bn.operator), span)
+        }
+    }
+}
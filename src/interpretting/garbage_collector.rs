@@ -1,44 +1,305 @@
-use std::collections::HashMap;
 use crate::interpretting::interpreter_utils::*;
+use crate::interpretting::variables::Variables;
+use crate::errors::*;
 
-// I think this is a tracing garbage collector,
-// although I haven't really done any research or seen other implementations,
-// I'm just diving in.
+// A mark and sweep collector. Marking starts from every scope that can
+// still be seen and the interpreter's temporary roots, and follows values
+// into objects, arrays and closures however deep they go.
+// Arrays and objects keep their contents in arenas of their own, which
+// are marked and swept alongside the values.
+// Anything that wasn't marked gets swept.
+//
+// Collections are triggered by heap growth rather than running all the
+// time. In incremental mode, marking is spread over many safepoints so
+// each pause only does a little work. While that's happening:
+//  - New values are marked straight away and queued so their children get
+//    looked at (they can't be garbage, something's about to hold them)
+//  - Writing a value into the heap marks what it points to (the write
+//    barrier), so moving a reference into something already looked at
+//    can't hide it from the collector
+//  - The roots are marked once more at the end, since scopes and temporary
+//    roots change without going through the barrier
 
-pub fn gc_collect(
-    alloced: &mut HashMap<usize, AllocedValue>,
-    scopestack: &mut Vec<HashMap<String, usize>>) {
+#[derive(Clone, Debug)]
+pub struct GcSettings {
+    // Collect once this many values are alive...
+    pub threshold: usize,
+    // ...or once the heap is this many times bigger than what
+    // survived the last collection, whichever is more
+    pub growth_factor: f64,
+    // Spread marking over lots of small steps
+    pub incremental: bool,
+    // How many values and scopes each incremental step looks at
+    pub step_size: usize
+}
+
+impl Default for GcSettings {
+    fn default () -> GcSettings {
+        GcSettings {
+            threshold: 1024,
+            growth_factor: 2.,
+            incremental: false,
+            step_size: 256
+        }
+    }
+}
+
+pub struct Collector {
+    pub settings: GcSettings,
+    // How many collections have finished
+    pub collections: usize,
+    // How many values survived the last one
+    pub live_after_last: usize,
+    // Set while an incremental collection is part way through marking
+    marking: Option<MarkState>
+}
+
+struct MarkState {
+    // Mark bits for the heap's slots
+    marked: Vec<bool>,
+    marked_arrays: Vec<bool>,
+    marked_objects: Vec<bool>,
+    marked_scopes: Vec<bool>,
+    // Marked, but not looked inside yet
+    worklist: Vec<ValueHandle>,
+    array_worklist: Vec<ArrayHandle>,
+    object_worklist: Vec<ObjectHandle>,
+    // Reachable, but maybe not marked yet
+    scope_queue: Vec<ScopeHandle>
+}
+
+impl Collector {
+    pub fn is_marking (&self) -> bool {
+        self.marking.is_some()
+    }
+
+    // Called with every new value
+    pub fn on_alloc (&mut self, idx: ValueHandle) {
+        if let Some(ms) = &mut self.marking {
+            ms.mark(idx);
+        }
+    }
+
+    pub fn on_alloc_array (&mut self, arr: ArrayHandle) {
+        if let Some(ms) = &mut self.marking {
+            ms.mark_array(arr);
+        }
+    }
+
+    pub fn on_alloc_object (&mut self, obj: ObjectHandle) {
+        if let Some(ms) = &mut self.marking {
+            ms.mark_object(obj);
+        }
+    }
+
+    // Called with every value written over another one in the heap
+    pub fn write_barrier (&mut self, value: &KaffeeValue) {
+        if let Some(ms) = &mut self.marking {
+            ms.mark_children(value);
+        }
+    }
+
+    fn should_collect (&self, live: usize) -> bool {
+        let grown = (self.live_after_last as f64 * self.settings.growth_factor) as usize;
+        live >= self.settings.threshold.max(grown)
+    }
+}
 
-    // No VISPLANE_OVERFLOWs here :P
-    let mut visplane: Vec<usize> = vec![];
+// Sets a mark bit, returning whether it wasn't set already
+fn set_bit (bits: &mut Vec<bool>, index: usize) -> bool {
+    if index >= bits.len() {
+        bits.resize(index + 1, false);
+    }
+    !std::mem::replace(&mut bits[index], true)
+}
 
-    for scope in scopestack {
-        for (_, idx) in scope {
-            visplane.push(*idx);
+fn is_set (bits: &[bool], index: usize) -> bool {
+    index < bits.len() && bits[index]
+}
+
+impl MarkState {
+    fn mark (&mut self, idx: ValueHandle) {
+        if set_bit(&mut self.marked, idx.index()) {
+            self.worklist.push(idx);
+        }
+    }
+
+    fn mark_array (&mut self, arr: ArrayHandle) {
+        if set_bit(&mut self.marked_arrays, arr.index()) {
+            self.array_worklist.push(arr);
+        }
+    }
+
+    fn mark_object (&mut self, obj: ObjectHandle) {
+        if set_bit(&mut self.marked_objects, obj.index()) {
+            self.object_worklist.push(obj);
+        }
+    }
 
-            // Follow object keys/values to avoid leaving
-            // dangling refs
-            let val = &alloced[idx];
-            if let KaffeeValue::Object(obj) = &val.value {
-                for i in 0..obj.keys.len() {
-                    visplane.push(obj.keys[i]);
-                    visplane.push(obj.values[i]);
+    // Marks everything a value points to
+    fn mark_children (&mut self, value: &KaffeeValue) {
+        match value {
+            KaffeeValue::Object(obj) => self.mark_object(*obj),
+            KaffeeValue::Array(arr) => self.mark_array(*arr),
+            // Closures keep the scopes they were made in alive
+            KaffeeValue::Function(f) => self.scope_queue.extend(&f.env),
+            _ => {}
+        }
+    }
+
+    fn mark_roots (&mut self, vars: &Variables) {
+        // Scopes in view, plus the ones callers will return to
+        self.scope_queue.extend(&vars.scopestack);
+        for stack in &vars.saved_stacks {
+            self.scope_queue.extend(stack);
+        }
+        for value in &vars.temp_roots {
+            self.mark_children(value);
+        }
+    }
+
+    // Does up to `budget` bits of work, returns true once there's nothing left
+    fn step (&mut self, vars: &Variables, mut budget: usize) -> bool {
+        while budget > 0 {
+            budget -= 1;
+
+            if let Some(id) = self.scope_queue.pop() {
+                // Already been here
+                if !set_bit(&mut self.marked_scopes, id.index()) { continue }
+                for (_, idx) in vars.scopes[id].bindings() {
+                    self.mark(idx);
                 }
+                continue
             }
-            // Follow array values for the same reason
-            if let KaffeeValue::Array(arr) = &val.value {
-                for idx in arr {
-                    visplane.push(idx.clone())
+
+            if let Some(obj) = self.object_worklist.pop() {
+                for (key, idx) in vars.objects[obj].properties.iter() {
+                    self.mark(*idx);
+                    // Arrays and objects can be keys too
+                    match key {
+                        ObjectKey::Array(arr) => self.mark_array(*arr),
+                        ObjectKey::Object(obj) => self.mark_object(*obj),
+                        _ => {}
+                    }
                 }
+                continue
+            }
+
+            if let Some(arr) = self.array_worklist.pop() {
+                for idx in &vars.arrays[arr] {
+                    self.mark(*idx);
+                }
+                continue
+            }
+
+            match self.worklist.pop() {
+                Some(idx) => self.mark_children(&vars.alloced[idx].value),
+                None => return true
             }
         }
+        self.worklist.is_empty() && self.array_worklist.is_empty() &&
+            self.object_worklist.is_empty() && self.scope_queue.is_empty()
     }
+}
 
-    // Clean up everything we can no longer see
-    let all_idx: Vec<usize> = alloced.keys().map(|x| x.clone()).collect();
-    for ai in all_idx {
-        if !visplane.contains(&ai) {
-            alloced.remove(&ai);
+pub fn new () -> Collector {
+    Collector {
+        settings: GcSettings::default(),
+        collections: 0,
+        live_after_last: 0,
+        marking: None
+    }
+}
+
+// Collects everything right now. Anything made during an incremental
+// collection survives it, so one that's part way through is finished
+// off and then there's a full one from scratch.
+pub fn gc_collect(vars: &mut Variables) {
+    if vars.gc.marking.is_some() {
+        finish(vars);
+    }
+    start_marking(vars);
+    finish(vars);
+}
+
+// Called by the interpreter wherever it's safe to collect (every
+// temporary value it's holding is rooted). Collects if the heap has
+// grown enough, or carries on with an incremental collection.
+// Then, if the heap is over its limits even after a full collection,
+// the script is out of memory.
+pub fn gc_safepoint(vars: &mut Variables) -> KaffeeResult<()> {
+    collect_if_needed(vars);
+
+    if vars.over_limits() {
+        gc_collect(vars);
+        if vars.over_limits() {
+            return Err(KaffeeError::new(ErrorKind::OutOfMemory,
+                format!("Out of memory ({} values, about {} bytes alive)", vars.heap_len(), vars.heap_bytes)))
         }
     }
+    Ok(())
+}
+
+fn collect_if_needed(vars: &mut Variables) {
+    if vars.gc.marking.is_none() {
+        if !vars.gc.should_collect(vars.heap_len()) { return }
+        if !vars.gc.settings.incremental {
+            return gc_collect(vars)
+        }
+        start_marking(vars);
+    }
+
+    let budget = vars.gc.settings.step_size.max(1);
+    let mut ms = vars.gc.marking.take().unwrap();
+    let done = ms.step(vars, budget);
+    vars.gc.marking = Some(ms);
+
+    if done {
+        finish(vars);
+    }
+}
+
+fn start_marking(vars: &mut Variables) {
+    let mut ms = MarkState {
+        marked: vec![false; vars.alloced.slot_count()],
+        marked_arrays: vec![false; vars.arrays.slot_count()],
+        marked_objects: vec![false; vars.objects.slot_count()],
+        marked_scopes: vec![],
+        worklist: vec![],
+        array_worklist: vec![],
+        object_worklist: vec![],
+        scope_queue: vec![]
+    };
+    ms.mark_roots(vars);
+    vars.gc.marking = Some(ms);
+}
+
+// Marks the roots again, marks whatever's left and sweeps
+fn finish(vars: &mut Variables) {
+    let mut ms = vars.gc.marking.take().unwrap();
+    ms.mark_roots(vars);
+    while !ms.step(vars, usize::MAX) {}
+
+    // Clean up everything we can no longer see
+    let mut freed_bytes = 0;
+    vars.alloced.retain(|idx, val| {
+        let keep = is_set(&ms.marked, idx.index());
+        if !keep { freed_bytes += val.approx_size() }
+        keep
+    });
+    vars.arrays.retain(|arr, items| {
+        let keep = is_set(&ms.marked_arrays, arr.index());
+        if !keep { freed_bytes += array_size(items) }
+        keep
+    });
+    vars.objects.retain(|obj, props| {
+        let keep = is_set(&ms.marked_objects, obj.index());
+        if !keep { freed_bytes += object_size(props) }
+        keep
+    });
+    vars.heap_bytes -= freed_bytes;
+    vars.scopes.retain(|id, _| is_set(&ms.marked_scopes, id.index()));
+
+    vars.gc.collections += 1;
+    vars.gc.live_after_last = vars.heap_len();
 }
@@ -0,0 +1,425 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::interpretting::interpreter_utils::*;
+use crate::interpretting::variables::Variables;
+
+// A copy of what's on the heap at one point in time, for hunting down
+// leaks and looking at the object graphs a script builds.
+// Values (and arrays' items and objects' entries, which live apart from
+// the values holding them) are listed in heap order along with what they point to, and
+// which root (a scope in view or that a caller will return to, or a value
+// held onto mid-call) first reaches them. Anything no root reaches is
+// garbage the GC hasn't gotten round to yet.
+
+pub struct HeapSnapshot {
+    pub values: Vec<SnapshotValue>,
+    pub scopes: Vec<SnapshotScope>,
+    // Roughly how many bytes the heap takes up
+    pub total_bytes: usize
+}
+
+// Something on the heap. Shown as v1v0 for values, a1v0 for arrays'
+// items and o1v0 for objects' entries.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SnapshotId {
+    Value(ValueHandle),
+    Array(ArrayHandle),
+    Object(ObjectHandle)
+}
+
+impl fmt::Display for SnapshotId {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotId::Value(idx) => write!(f, "v{}", idx),
+            SnapshotId::Array(arr) => write!(f, "a{}", arr),
+            SnapshotId::Object(obj) => write!(f, "o{}", obj)
+        }
+    }
+}
+
+pub struct SnapshotValue {
+    pub id: SnapshotId,
+    // Object, Array, String, ... or ArrayItems and ObjectEntries
+    pub kind: &'static str,
+    // A short preview, eg. "hello" or [3 items]
+    pub label: String,
+    pub size: usize,
+    pub constant: bool,
+    // What this points to, named by key or index
+    pub edges: Vec<(String, SnapshotId)>,
+    // Scopes a closure keeps alive
+    pub captures: Vec<usize>,
+    // The first root found to reach this value, in the order the GC
+    // marks them, or None if it's garbage
+    pub root: Option<SnapshotRoot>
+}
+
+// What keeps a value alive
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SnapshotRoot {
+    // A scope, by id
+    Scope(usize),
+    // Something the interpreter is holding onto partway through running
+    // a statement, eg. a call's arguments before they're in scope
+    Temporary
+}
+
+impl fmt::Display for SnapshotRoot {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotRoot::Scope(id) => write!(f, "s{}", id),
+            SnapshotRoot::Temporary => write!(f, "temporary")
+        }
+    }
+}
+
+pub struct SnapshotScope {
+    pub id: usize,
+    // Where it sits in the current scopestack, if it's in there at all
+    pub depth: Option<usize>,
+    pub is_root: bool,
+    // Sorted by name so snapshots of the same program look the same
+    pub bindings: Vec<(String, SnapshotId)>
+}
+
+// Strings in labels are cut down to this many characters
+const MAX_LABEL_CHARS: usize = 32;
+
+impl HeapSnapshot {
+    pub fn value (&self, id: SnapshotId) -> Option<&SnapshotValue> {
+        self.values.iter().find(|v| v.id == id)
+    }
+
+    // Values no root can reach
+    pub fn unreachable (&self) -> impl Iterator<Item = &SnapshotValue> {
+        self.values.iter().filter(|v| v.root.is_none())
+    }
+
+    pub fn to_json (&self) -> String {
+        let mut out = String::from("{\n  \"total_bytes\": ");
+        out += &self.total_bytes.to_string();
+
+        out += ",\n  \"scopes\": [";
+        for (i, scope) in self.scopes.iter().enumerate() {
+            if i > 0 { out += "," }
+            let depth = match scope.depth {
+                Some(d) => d.to_string(),
+                None => String::from("null")
+            };
+            let bindings: Vec<String> = scope.bindings.iter()
+                .map(|(name, idx)| format!("{{\"name\": {}, \"value\": \"{}\"}}", json_string(name), idx))
+                .collect();
+            out += &format!("\n    {{\"id\": {}, \"depth\": {}, \"root\": {}, \"bindings\": [{}]}}",
+                scope.id, depth, scope.is_root, bindings.join(", "));
+        }
+
+        out += "\n  ],\n  \"values\": [";
+        for (i, v) in self.values.iter().enumerate() {
+            if i > 0 { out += "," }
+            let edges: Vec<String> = v.edges.iter()
+                .map(|(name, idx)| format!("{{\"name\": {}, \"to\": \"{}\"}}", json_string(name), idx))
+                .collect();
+            let root = match v.root {
+                Some(root) => format!("\"{}\"", root),
+                None => String::from("null")
+            };
+            out += &format!("\n    {{\"id\": \"{}\", \"type\": \"{}\", \"label\": {}, \"size\": {}, \"constant\": {}, \"edges\": [{}], \"captures\": {:?}, \"root\": {}}}",
+                v.id, v.kind, json_string(&v.label), v.size, v.constant, edges.join(", "), v.captures, root);
+        }
+        out += "\n  ]\n}\n";
+        out
+    }
+
+    // Graphviz, eg. `dot -Tsvg heap.dot -o heap.svg`.
+    // Scopes are boxes, values are ellipses, and garbage is greyed out.
+    pub fn to_dot (&self) -> String {
+        let mut out = String::from("digraph heap {\n    rankdir=LR;\n    node [fontname=\"monospace\"];\n");
+
+        for scope in &self.scopes {
+            let title = match scope.depth {
+                Some(d) => format!("scope {} (depth {})", scope.id, d),
+                None => format!("scope {}", scope.id)
+            };
+            let style = if scope.is_root { "bold" } else { "dashed" };
+            out += &format!("    s{} [shape=box, style={}, label={}];\n", scope.id, style, dot_string(&title));
+            for (name, idx) in &scope.bindings {
+                out += &format!("    s{} -> {} [label={}];\n", scope.id, idx, dot_string(name));
+            }
+        }
+
+        for v in &self.values {
+            let label = format!("{}\n{}: {}", v.id, v.kind, v.label);
+            let style = if v.root.is_none() { ", style=filled, fillcolor=lightgrey" } else { "" };
+            out += &format!("    {} [label={}{}];\n", v.id, dot_string(&label), style);
+            for (name, idx) in &v.edges {
+                out += &format!("    {} -> {} [label={}];\n", v.id, idx, dot_string(name));
+            }
+            for id in &v.captures {
+                out += &format!("    {} -> s{} [style=dashed, label=\"env\"];\n", v.id, id);
+            }
+        }
+
+        out += "}\n";
+        out
+    }
+}
+
+// Plain text, for --print-heap
+impl fmt::Display for HeapSnapshot {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Heap: {} values, about {} bytes", self.values.len(), self.total_bytes)?;
+        for v in &self.values {
+            let garbage = if v.root.is_none() { " (unreachable)" } else { "" };
+            writeln!(f, "{} - {}: {} - {} bytes{}", v.id, v.kind, v.label, v.size, garbage)?;
+            for (name, idx) in &v.edges {
+                writeln!(f, "    - {} -> {}", name, idx)?;
+            }
+            for id in &v.captures {
+                writeln!(f, "    - env -> scope {}", id)?;
+            }
+        }
+
+        writeln!(f)?;
+        for scope in &self.scopes {
+            match scope.depth {
+                Some(d) => writeln!(f, "Scope {} (depth {}):", scope.id, d)?,
+                None => writeln!(f, "Scope {} (captured):", scope.id)?
+            }
+            for (name, idx) in &scope.bindings {
+                writeln!(f, " - \"{}\" - {}", name, idx)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn take (vars: &Variables) -> HeapSnapshot {
+    // The same roots the GC marks from, marked in one go
+    let mut roots = vec![];
+    for id in vars.scopestack.iter().chain(vars.saved_stacks.iter().flatten()) {
+        if !roots.contains(id) { roots.push(*id) }
+    }
+
+    let mut marks = Marks { reached_by: HashMap::new(), seen_scopes: HashSet::new() };
+    for root in &roots {
+        marks.mark(vars, SnapshotRoot::Scope(root.index()), vec![*root], vec![]);
+    }
+    for value in &vars.temp_roots {
+        let (edges, captures) = edges_of(value);
+        let ids = edges.into_iter().map(|(_, to)| to).collect();
+        marks.mark(vars, SnapshotRoot::Temporary, captures, ids);
+    }
+    let mut reached_by = marks.reached_by;
+
+    let mut values: Vec<SnapshotValue> = vars.alloced.iter().map(|(idx, v)| {
+        let id = SnapshotId::Value(idx);
+        let (edges, captures) = edges_of(&v.value);
+        SnapshotValue {
+            id,
+            kind: kind_name(&v.value),
+            label: label_for(vars, &v.value),
+            size: v.approx_size(),
+            constant: v.constant,
+            edges,
+            captures: captures.iter().map(|id| id.index()).collect(),
+            root: reached_by.remove(&id)
+        }
+    }).collect();
+
+    for (arr, items) in vars.arrays.iter() {
+        let id = SnapshotId::Array(arr);
+        values.push(SnapshotValue {
+            id,
+            kind: "ArrayItems",
+            label: format!("[{} items]", items.len()),
+            size: array_size(items),
+            constant: false,
+            edges: item_edges(items),
+            captures: vec![],
+            root: reached_by.remove(&id)
+        });
+    }
+
+    for (obj, props) in vars.objects.iter() {
+        let id = SnapshotId::Object(obj);
+        values.push(SnapshotValue {
+            id,
+            kind: "ObjectEntries",
+            label: format!("{{{} keys}}", props.len()),
+            size: object_size(props),
+            constant: false,
+            edges: entry_edges(vars, props),
+            captures: vec![],
+            root: reached_by.remove(&id)
+        });
+    }
+
+    // Scopes are shown by their index, which is unique among the live ones
+    let scopes = vars.scopes.iter().map(|(id, scope)| {
+        let mut bindings: Vec<(String, SnapshotId)> = scope.bindings()
+            .map(|(name, idx)| (name.clone(), SnapshotId::Value(idx)))
+            .collect();
+        bindings.sort_by(|a, b| a.0.cmp(&b.0));
+
+        SnapshotScope {
+            id: id.index(),
+            depth: vars.scopestack.iter().position(|s| *s == id),
+            is_root: roots.contains(&id),
+            bindings
+        }
+    }).collect();
+
+    HeapSnapshot {
+        values,
+        scopes,
+        total_bytes: vars.heap_bytes
+    }
+}
+
+// What's been reached so far, and by which root
+struct Marks {
+    reached_by: HashMap<SnapshotId, SnapshotRoot>,
+    seen_scopes: HashSet<ScopeHandle>
+}
+
+impl Marks {
+    // Marks everything reachable from some scopes and values, following
+    // closures into the scopes they captured. Anything an earlier root
+    // already reached is left to it.
+    fn mark (&mut self, vars: &Variables, root: SnapshotRoot, mut scope_queue: Vec<ScopeHandle>, mut worklist: Vec<SnapshotId>) {
+        loop {
+            if let Some(id) = scope_queue.pop() {
+                if !self.seen_scopes.insert(id) { continue }
+                if let Some(scope) = vars.scopes.get(id) {
+                    worklist.extend(scope.bindings().map(|(_, idx)| SnapshotId::Value(idx)));
+                }
+                continue
+            }
+
+            let idx = match worklist.pop() {
+                Some(idx) => idx,
+                None => return
+            };
+            if self.reached_by.contains_key(&idx) { continue }
+            self.reached_by.insert(idx, root);
+
+            match idx {
+                SnapshotId::Value(idx) => if let Some(v) = vars.alloced.get(idx) {
+                    let (edges, captures) = edges_of(&v.value);
+                    worklist.extend(edges.into_iter().map(|(_, to)| to));
+                    scope_queue.extend(captures);
+                },
+                SnapshotId::Array(arr) => if let Some(items) = vars.arrays.get(arr) {
+                    worklist.extend(items.iter().map(|idx| SnapshotId::Value(*idx)));
+                },
+                SnapshotId::Object(obj) => if let Some(props) = vars.objects.get(obj) {
+                    worklist.extend(entry_edges(vars, props).into_iter().map(|(_, to)| to));
+                }
+            }
+        }
+    }
+}
+
+fn edges_of (value: &KaffeeValue) -> (Vec<(String, SnapshotId)>, Vec<ScopeHandle>) {
+    match value {
+        KaffeeValue::Object(obj) => (vec![(String::from("entries"), SnapshotId::Object(*obj))], vec![]),
+        KaffeeValue::Array(arr) => (vec![(String::from("items"), SnapshotId::Array(*arr))], vec![]),
+        KaffeeValue::Function(f) => (vec![], f.env.clone()),
+        _ => (vec![], vec![])
+    }
+}
+
+pub fn kind_name (value: &KaffeeValue) -> &'static str {
+    match value {
+        KaffeeValue::Number(_) => "Number",
+        KaffeeValue::String(_) => "String",
+        KaffeeValue::Boolean(_) => "Boolean",
+        KaffeeValue::Null => "Null",
+        KaffeeValue::NativeFunction(_) => "NativeFunction",
+        KaffeeValue::Function(_) => "Function",
+        KaffeeValue::Object(_) => "Object",
+        KaffeeValue::Array(_) => "Array"
+    }
+}
+
+fn entry_edges (vars: &Variables, obj: &ObjectValue) -> Vec<(String, SnapshotId)> {
+    let mut edges = vec![];
+    for (i, (key, idx)) in obj.properties.iter().enumerate() {
+        // Value edges are named after their keys
+        let name = match key {
+            ObjectKey::String(st) => st.to_string(),
+            other => label_for(vars, &other.to_value())
+        };
+        edges.push((name, SnapshotId::Value(*idx)));
+
+        // Arrays and objects used as keys are kept alive by it too
+        match key {
+            ObjectKey::Array(arr) => edges.push((format!("key {}", i), SnapshotId::Array(*arr))),
+            ObjectKey::Object(obj) => edges.push((format!("key {}", i), SnapshotId::Object(*obj))),
+            _ => {}
+        }
+    }
+    edges
+}
+
+fn item_edges (items: &[ValueHandle]) -> Vec<(String, SnapshotId)> {
+    items.iter().enumerate()
+        .map(|(i, idx)| (format!("[{}]", i), SnapshotId::Value(*idx)))
+        .collect()
+}
+
+fn label_for (vars: &Variables, value: &KaffeeValue) -> String {
+    match value {
+        KaffeeValue::Number(n) => n.to_string(),
+        KaffeeValue::String(s) => {
+            if s.chars().count() > MAX_LABEL_CHARS {
+                format!("\"{}...\"", s.chars().take(MAX_LABEL_CHARS).collect::<String>())
+            } else {
+                format!("\"{}\"", s)
+            }
+        },
+        KaffeeValue::Boolean(b) => b.to_string(),
+        KaffeeValue::Null => String::from("null"),
+        KaffeeValue::NativeFunction(nm) => format!("{} ({} args)", nm.name, nm.arg_count),
+        KaffeeValue::Function(f) => format!("{}({})", f.display_name(), f.args.join(", ")),
+        KaffeeValue::Object(obj) => match vars.objects.get(*obj) {
+            Some(props) => format!("{{{} keys}}", props.len()),
+            None => String::from("{freed}")
+        },
+        KaffeeValue::Array(arr) => match vars.arrays.get(*arr) {
+            Some(items) => format!("[{} items]", items.len()),
+            None => String::from("[freed]")
+        }
+    }
+}
+
+fn json_string (s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn dot_string (s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
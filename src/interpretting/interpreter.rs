@@ -1,350 +1,861 @@
-use crate::parsing::printer::print_ast_node;
 use crate::parsing::parser;
 use crate::parsing::ast_utils::*;
+use crate::parsing::span::Span;
 use crate::interpretting::interpreter_utils::*;
 use crate::interpretting::variables::Variables;
 use crate::interpretting::variables;
 use crate::std_lib::functions::*;
 use crate::std_lib::operators;
 use crate::interpretting::garbage_collector;
+use crate::interpretting::garbage_collector::GcSettings;
+use crate::interpretting::heap_snapshot;
+use crate::interpretting::heap_snapshot::HeapSnapshot;
+use crate::interpretting::compiler;
+use crate::interpretting::vm;
+use crate::interpretting::resolver;
+use crate::interpretting::optimizer;
+use crate::errors::*;
+use crate::interpretting::bytecode::Chunk;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
 use std::time::Instant;
 
-/*
-    TODO: Instead of panicking, throw exceptions within the
-          language which can be caught etc.
-*/
 // TODO: So many methods unnecessarily take &mut self references
 
 pub struct Interpreter {
     pub ast: Vec<ASTNode>,
-    pub vars: Variables
+    pub vars: Variables,
+    // Every piece of code that's been parsed, indexed by
+    // the source number in spans
+    pub sources: Vec<String>,
+    // The calls currently running, outermost first
+    pub call_stack: Vec<StackFrame>,
+    pub limits: ExecutionLimits,
+    // How many steps have been run so far, for limits.max_steps
+    pub steps: u64,
+    // Whether to walk the AST or compile it to bytecode
+    pub backend: Backend,
+    // Whether to simplify code (constant folding etc.) before running it
+    pub optimize: bool,
+    // Set by a return that ends in a tail call, for the
+    // function it's returning from to make
+    tail_call: Option<TailCall>,
+    // Bytecode for the bodies of functions the tree walker made, so the
+    // VM only compiles them once. Keyed by where the body is. The weak
+    // reference keeps that address from being reused for another body
+    // after this one's dropped, without keeping the body itself around.
+    compiled: CompiledBodies
 }
 
+type CompiledBodies = HashMap<*const Vec<ASTNode>, (Weak<Vec<ASTNode>>, Rc<Chunk>)>;
+
+
 impl Interpreter {
-    pub fn run (&mut self) {
-        println!("\nProgram output:");
+    pub fn run (&mut self) -> KaffeeResult<()> {
+        // Done here rather than in new so the host can add globals first
+        resolver::resolve(&mut self.ast, &mut self.vars)?;
+        if self.optimize {
+            optimizer::optimize(&mut self.ast);
+        }
+        let depth = self.vars.scopestack.len();
+
+        // Borrowed out while it runs rather than copied a statement at a time
+        let ast = std::mem::take(&mut self.ast);
+        let res = ast.iter().try_for_each(|node| match self.backend {
+            Backend::TreeWalker => self.eval_node(node).map(|_| ()),
+            Backend::Bytecode => {
+                let chunk = compiler::compile_statement(node);
+                vm::run(self, chunk).map(|_| ())
+            }
+        });
+        self.ast = ast;
+
+        if res.is_err() {
+            self.vars.scopestack.truncate(depth);
+        }
+        res
+    }
+
+    // Parses and runs a snippet against the existing globals.
+    // Returns the value of the last statement if it was an expression,
+    // otherwise null.
+    pub fn eval (&mut self, code: String) -> KaffeeResult<KaffeeValue> {
+        let ast = self.parse(code)?;
+
+        let mut last = KaffeeValue::Null;
+        for node in &ast {
+            last = self.eval_statement(node)?.unwrap_or(KaffeeValue::Null);
+        }
+        Ok(last)
+    }
+
+    // Parses some more code for this interpreter to run, keeping hold of
+    // it so errors can show where they are. Its variables are resolved
+    // against the current scope, so it needs to run there. It's optimized
+    // too, unless optimize is off.
+    pub fn parse (&mut self, code: String) -> KaffeeResult<Vec<ASTNode>> {
+        let source = self.sources.len();
+        self.sources.push(code.clone());
+        let mut ast = parser::new_with_source(code, source)?.generate_ast()?;
+        resolver::resolve(&mut ast, &mut self.vars)?;
+        if self.optimize {
+            optimizer::optimize(&mut ast);
+        }
+        Ok(ast)
+    }
+
+    // Formats an error with the line of code it came from
+    pub fn render_error (&self, err: &KaffeeError) -> String {
+        match err.span {
+            Some(span) if span.source < self.sources.len() => {
+                err.render(&self.sources[span.source])
+            },
+            // No code to show, but still show the trace
+            _ => err.render("")
+        }
+    }
+
+    // Makes a Rust function callable from Kaffee. Registering a name
+    // that already exists (eg. println) replaces it.
+    pub fn register_function (&mut self, name: &str, arg_count: usize, func: NativeFuncSignature) {
+        let mapping = NativeMapping {
+            name: name.to_string(),
+            arg_count,
+            func
+        };
+        self.vars.set_in_scope(variables::STD_LIB_SCOPE, name, KaffeeValue::NativeFunction(mapping), true);
+    }
 
+    fn load_globals (&mut self) {
         self.vars.new_scope();
         // Put funcs like println in the global scope
         self.load_std_lib();
 
         // User global scope is above std_lib global scope
         self.vars.new_scope();
+    }
 
-        let now = Instant::now();
+    // Runs a top-level statement in whatever scope is current,
+    // returning its value if it was an expression (used by the REPL).
+    // On an error, any scopes it entered are left.
+    pub fn eval_statement (&mut self, node: &ASTNode) -> KaffeeResult<Option<KaffeeValue>> {
+        let depth = self.vars.scopestack.len();
+
+        let res = if self.backend == Backend::Bytecode {
+            let value = vm::run(self, compiler::compile_statement(node));
+            value.map(|v| if node.is_expression() { Some(v) } else { None })
+        } else if node.is_expression() {
+            // Counted like any other statement
+            self.tick()
+                .and_then(|_| self.resolve_node(node))
+                .map(Some)
+                .map_err(|e| e.with_span(node.span))
+        } else {
+            self.eval_node(node).map(|_| None)
+        };
 
-        for i in 0..self.ast.len() {
-            let node = self.ast[i].clone();
-            self.eval_node(&node);
+        if res.is_err() {
+            self.vars.scopestack.truncate(depth);
         }
-
-        println!("\nProgram execution time: {}ms", now.elapsed().as_millis());
-
-        println!("\nAllocced values:");
-        self.vars.print_allocced();
-        println!("");
-        self.vars.print_scopestack();
+        res
     }
 
     fn load_std_lib (&mut self) {
         for mapping in get_std_lib_mappings() {
-            self.vars.alloc_in_scope(
+            self.vars.set_in_scope(
+                variables::STD_LIB_SCOPE,
                 &mapping.name,
                 KaffeeValue::NativeFunction(mapping.clone()),
                 true)
         }
     }
 
-    fn gc_collect (&mut self) {
+    // Frees every value the program can no longer reach. This also
+    // happens by itself as the heap grows.
+    pub fn gc_collect (&mut self) {
         // Garbage collection
-        garbage_collector::gc_collect(&mut self.vars.alloced, &mut self.vars.scopestack)
+        garbage_collector::gc_collect(&mut self.vars)
+    }
+
+    // When to collect, and whether to do it incrementally
+    pub fn gc_settings (&mut self) -> &mut GcSettings {
+        &mut self.vars.gc.settings
+    }
+
+    // What's on the heap right now, and what can reach it
+    pub fn heap_snapshot (&self) -> HeapSnapshot {
+        heap_snapshot::take(&self.vars)
+    }
+
+    // A function's body as bytecode, compiling it if it hasn't been yet
+    pub(crate) fn function_code (&mut self, fd: &FunctionDefinition, span: Span) -> Rc<Chunk> {
+        if let Some(chunk) = &fd.code {
+            return chunk.clone()
+        }
+        let key = Rc::as_ptr(&fd.body);
+        if let Some((_, chunk)) = self.compiled.get(&key) {
+            return chunk.clone()
+        }
+
+        // Every so often, forget the bodies that have been dropped
+        let len = self.compiled.len();
+        if len >= 64 && len.is_power_of_two() {
+            self.compiled.retain(|_, (body, _)| body.strong_count() > 0);
+        }
+        let chunk = Rc::new(compiler::compile_function(&fd.body, span));
+        self.compiled.insert(key, (Rc::downgrade(&fd.body), chunk.clone()));
+        chunk
+    }
+
+    // Somewhere the GC is allowed to run, if it wants to.
+    // Fails if the heap's still over its limits after collecting.
+    pub(crate) fn gc_safepoint (&mut self) -> KaffeeResult<()> {
+        garbage_collector::gc_safepoint(&mut self.vars)
     }
 
     // Returns (Did it return early?, what did it return)
-    fn eval_node (&mut self, node: &ASTNode) -> (BreakType, KaffeeValue) {
-        match node {
-            ASTNode::BlockStatement(bs) => {
-                self.vars.new_scope();
-                for n in bs {
-                    // If we eval a sub-block and it returns, we need to return, too
-                    let (bt, kv) = self.eval_node(n);
-                    if bt != BreakType::None {
-                        self.vars.pop_scope();
-                        return (bt, kv)
-                    }
+    fn eval_node (&mut self, node: &ASTNode) -> KaffeeResult<(BreakType, KaffeeValue)> {
+        self.tick()
+            .and_then(|_| self.eval_node_kind(node))
+            .map_err(|e| e.with_span(node.span))
+    }
+
+    // Counts a step towards the step budget, and every so often checks
+    // the deadline (checking the clock every step would be slow)
+    pub(crate) fn tick (&mut self) -> KaffeeResult<()> {
+        self.steps += 1;
+
+        if let Some(max) = self.limits.max_steps {
+            if self.steps > max {
+                return Err(KaffeeError::new(ErrorKind::ExecutionLimit,
+                    format!("Execution limit exceeded, ran for more than {} steps", max)))
+            }
+        }
+
+        if self.steps.is_multiple_of(1024) {
+            if let Some(deadline) = self.limits.deadline {
+                if Instant::now() >= deadline {
+                    return Err(KaffeeError::new(ErrorKind::ExecutionLimit,
+                        String::from("Execution limit exceeded, ran past the deadline")))
                 }
-                // TODO: Remove this repetition
+            }
+        }
+        Ok(())
+    }
+
+    // Like resolve_node_kind, this avoids ? to keep its stack frame small
+    fn eval_node_kind (&mut self, node: &ASTNode) -> KaffeeResult<(BreakType, KaffeeValue)> {
+        match &node.kind {
+            NodeKind::BlockStatement(bs) => {
+                self.vars.new_scope();
+                let res = self.eval_block(bs);
                 self.vars.pop_scope();
-                self.gc_collect();
-            },
-            ASTNode::Declaration(dcl) => self.define_variable(dcl),
-            ASTNode::Assignment(asn) => self.assign_variable(asn),
-            ASTNode::FunctionCall(cp) => { self.eval_call(&cp); },
-            ASTNode::FunctionDefinition(fd) => self.eval_function_definition(&fd),
-            ASTNode::IfStatement(ifs) => { return self.eval_if_stmnt(&ifs) },
-            ASTNode::ReturnStatement(rs) => {
-                return (BreakType::Return, self.resolve_node(rs.as_ref()))
+
+                // If we eval a sub-block and it returns, we need to return, too
+                res
             },
-            ASTNode::ContinueStatement => {
-                return (BreakType::Continue, KaffeeValue::Null)
+            NodeKind::Declaration(dcl) => no_break(self.define_variable(dcl)),
+            NodeKind::Assignment(asn) => no_break(self.assign_variable(asn)),
+            NodeKind::FunctionCall(cp) => no_break(self.eval_call(cp, node.span)),
+            NodeKind::UpdateNode(up) => no_break(self.resolve_update(up)),
+            NodeKind::FunctionDefinition(fd) => no_break(self.eval_function_definition(fd)),
+            NodeKind::IfStatement(ifs) => self.eval_if_stmnt(ifs),
+            NodeKind::ReturnStatement(rs) => match &rs.kind {
+                NodeKind::FunctionCall(cp) if cp.tail => {
+                    self.eval_tail_call(cp, rs.span).map(|val| (BreakType::Return, val))
+                },
+                _ => self.resolve_node(rs.as_ref()).map(|val| (BreakType::Return, val))
             },
-            ASTNode::BreakStatement => {
-                return (BreakType::Break, KaffeeValue::Null)
+            NodeKind::ContinueStatement => Ok((BreakType::Continue, KaffeeValue::Null)),
+            NodeKind::BreakStatement => Ok((BreakType::Break, KaffeeValue::Null)),
+            NodeKind::WhileLoop(wp) => self.eval_while_loop(wp),
+            NodeKind::TryStatement(tp) => self.eval_try_statement(tp),
+            NodeKind::ThrowStatement(ts) => {
+                self.eval_throw(ts.as_ref()).and_then(Err)
             },
-            ASTNode::WhileLoop(wp) => { return self.eval_while_loop(&wp) },
             _ => {
-                print_ast_node(node, 0);
-                panic!("Unsupported executable node")
+                Err(KaffeeError::new(ErrorKind::Syntax,
+                    String::from("This expression can't be used as a statement")))
             }
         }
+    }
 
-        (BreakType::None, KaffeeValue::Null)
+    // Runs the statements of a block in the current scope,
+    // stopping early if one of them breaks out
+    fn eval_block (&mut self, bs: &[ASTNode]) -> KaffeeResult<(BreakType, KaffeeValue)> {
+        for n in bs {
+            let (bt, kv) = self.eval_node(n)?;
+            if bt != BreakType::None {
+                return Ok((bt, kv))
+            }
+            self.gc_safepoint().map_err(|e| e.with_span(n.span))?;
+        }
+        Ok((BreakType::None, KaffeeValue::Null))
     }
 
-    fn eval_while_loop (&mut self, wp: &WhileProperties) -> (BreakType, KaffeeValue) {
-        while self.node_as_bool(wp.check.as_ref()) {
-            let (b_type, ret_val) = self.eval_node(wp.body.as_ref());
+    fn eval_while_loop (&mut self, wp: &WhileProperties) -> KaffeeResult<(BreakType, KaffeeValue)> {
+        while self.node_as_bool(wp.check.as_ref())? {
+            self.tick()?;
+            let (b_type, ret_val) = self.eval_node(wp.body.as_ref())?;
             if b_type == BreakType::Return {
-                return (b_type, ret_val)
+                return Ok((b_type, ret_val))
             }
+            self.gc_safepoint().map_err(|e| e.with_span(wp.body.span))?;
             if b_type == BreakType::Break {
                 break;
             }
         }
 
-        (BreakType::None, KaffeeValue::Null)
+        Ok((BreakType::None, KaffeeValue::Null))
     }
 
-    fn node_as_bool (&mut self, node: &ASTNode) -> bool {
-        let res = self.resolve_node(node);
-        // Truthy coercion
-        match res {
-            KaffeeValue::Boolean(bl) => bl,
-            KaffeeValue::Null => false,
-            _ => true
+    fn eval_throw (&mut self, node: &ASTNode) -> KaffeeResult<KaffeeError> {
+        let value = self.resolve_node(node)?;
+        Ok(self.thrown_error(value))
+    }
+
+    pub(crate) fn thrown_error (&mut self, value: KaffeeValue) -> KaffeeError {
+        // Rethrowing a caught error keeps its message
+        let mut message = None;
+        if let KaffeeValue::Object(obj) = &value {
+            let key = KaffeeValue::String("message".into());
+            if let Some(idx) = self.vars.lookup_object_value_index(*obj, &key) {
+                if let KaffeeValue::String(st) = &self.vars.alloced[idx].value {
+                    message = Some(st.to_string())
+                }
+            }
+        }
+        let message = match message {
+            Some(m) => m,
+            None => rust_stringify(&value, &mut self.vars)
+        };
+
+        KaffeeError::thrown(value, message)
+    }
+
+    fn eval_try_statement (&mut self, tp: &TryProperties) -> KaffeeResult<(BreakType, KaffeeValue)> {
+        let depth = self.vars.scopestack.len();
+        let mut res = self.eval_node(tp.body.as_ref());
+
+        if let Some(catch_body) = &tp.catch_body {
+            if uncatchable(&res) { return res }
+
+            if let Err(e) = res {
+                self.vars.scopestack.truncate(depth);
+                let caught = self.error_to_value(e);
+
+                // The caught value gets a scope around the catch block
+                self.vars.new_scope();
+                res = match &tp.catch_name {
+                    Some(name) => self.vars.alloc_in_scope(name, None, caught, false)
+                        .and_then(|_| self.eval_node(catch_body)),
+                    None => self.eval_node(catch_body)
+                };
+                self.vars.pop_scope();
+            }
         }
+
+        if let Some(finally_body) = &tp.finally_body {
+            if uncatchable(&res) { return res }
+            self.vars.scopestack.truncate(depth);
+
+            // Leaving the finally early (return, throw etc.)
+            // overrides whatever happened before it
+            let (bt, kv) = self.rooted(|this| {
+                // Whatever's waiting to be returned or thrown
+                // has to survive the finally block
+                match &res {
+                    Ok((_, kv)) => this.vars.root(kv),
+                    Err(e) => if let Some(value) = &e.value { this.vars.root(value) }
+                }
+                this.eval_node(finally_body)
+            })?;
+            if bt != BreakType::None {
+                return Ok((bt, kv))
+            }
+        }
+
+        res
     }
 
-    fn eval_if_stmnt(&mut self, ifp: &IfProperties) -> (BreakType, KaffeeValue) {
-        let check = self.node_as_bool(ifp.check_exp.as_ref());
+    // Thrown values are caught as they are, errors from the
+    // interpreter itself become { message, kind, stack } objects.
+    // Thrown objects get a stack too, unless they have one already.
+    pub(crate) fn error_to_value (&mut self, err: KaffeeError) -> KaffeeValue {
+        let stack = KaffeeValue::String(err.render_trace().into());
+
+        if let Some(value) = err.value {
+            if let KaffeeValue::Object(obj) = *value {
+                let key = ObjectKey::String("stack".into());
+                if self.vars.objects[obj].get(&key).is_none() {
+                    self.vars.insert_into_object(key, stack, obj);
+                }
+            }
+            return *value
+        }
+
+        self.vars.new_object(vec![
+            ("message", KaffeeValue::String(err.message.into())),
+            ("kind", KaffeeValue::String(err.kind.name().into())),
+            ("stack", stack)
+        ])
+    }
+
+    fn node_as_bool (&mut self, node: &ASTNode) -> KaffeeResult<bool> {
+        let res = self.resolve_node(node)?;
+        // Truthy coercion
+        Ok(operators::is_truthy(&res))
+    }
+
+    fn eval_if_stmnt(&mut self, ifp: &IfProperties) -> KaffeeResult<(BreakType, KaffeeValue)> {
+        let check = self.node_as_bool(ifp.check_exp.as_ref())?;
         if check {
-            return self.eval_node(ifp.body.as_ref());
+            self.eval_node(ifp.body.as_ref())
         } else {
             if let Some(en) = &ifp.else_exp {
-                return self.eval_node(en.as_ref());
+                self.eval_node(en.as_ref())
             } else {
-                return (BreakType::None, KaffeeValue::Null)
+                Ok((BreakType::None, KaffeeValue::Null))
             }
         }
     }
 
     fn ast_func_to_value (&mut self, fd: &FunctionDefinitionProperties) -> KaffeeValue {
-        KaffeeValue::Function(FunctionDefinition {
-            args: fd.args.clone(),
-            body: fd.body.clone()
-        })
+        self.vars.new_function(fd.name.clone(), fd.args.clone(), fd.body.clone(), None)
     }
 
-    fn eval_function_definition (&mut self, fd: &FunctionDefinitionProperties) {
+    fn eval_function_definition (&mut self, fd: &FunctionDefinitionProperties) -> KaffeeResult<()> {
         let kv_fn = self.ast_func_to_value(fd);
-        self.vars.alloc_in_scope(&fd.name, kv_fn, false);
+        self.vars.alloc_in_scope(&fd.name, fd.slot, kv_fn, false)
     }
 
     // Returns the "return value" of the function
-    fn eval_call (&mut self, cp: &CallProperties) -> KaffeeValue {
-        let callee = self.resolve_node(cp.callee.as_ref());
-        if let KaffeeValue::NativeFunction(nf) = callee {
-            let rargs: Vec<KaffeeValue> = cp.args.iter().map(|x| self.resolve_node(x)).collect();
+    fn eval_call (&mut self, cp: &CallProperties, span: Span) -> KaffeeResult<KaffeeValue> {
+        self.tick()?;
+        let callee = self.resolve_node(cp.callee.as_ref())?;
+
+        self.rooted(|this| {
+            // The callee might be the only thing holding onto its
+            // environment, eg. make()(x)
+            this.vars.root(&callee);
+            // Arguments are resolved by the caller, in the caller's scope
+            let rargs = this.resolve_rooted(&cp.args)?;
+
+            match &callee {
+                KaffeeValue::NativeFunction(nf) => this.call_native(nf, rargs, span),
+                KaffeeValue::Function(f) => {
+                    this.check_call(f, rargs.len())?;
+                    this.eval_userfn_call(rargs, f, span)
+                },
+                _ => Err(uncallable().with_span(cp.callee.span))
+            }
+        })
+    }
 
-            if rargs.len() != nf.arg_count {
-                panic!("{} takes {} arguments, but {} were supplied", nf.name, nf.arg_count, rargs.len())
+    // Works out the callee and arguments of a call in tail position. Calls
+    // to Kaffee functions are left for the function that's returning to
+    // make once it's gone, and it returns null in the meantime.
+    fn eval_tail_call (&mut self, cp: &CallProperties, span: Span) -> KaffeeResult<KaffeeValue> {
+        self.tick().map_err(|e| e.with_span(span))?;
+        let callee = self.resolve_node(cp.callee.as_ref())?;
+
+        self.rooted(|this| {
+            this.vars.root(&callee);
+            let rargs = this.resolve_rooted(&cp.args)?;
+
+            match callee {
+                KaffeeValue::NativeFunction(nf) => this.call_native(&nf, rargs, span),
+                KaffeeValue::Function(func) => {
+                    this.tail_call = Some(TailCall { func, args: rargs, span });
+                    Ok(KaffeeValue::Null)
+                },
+                _ => Err(uncallable().with_span(cp.callee.span))
             }
+        })
+    }
 
-            (nf.func)(rargs, &mut self.vars)
-        } else if let KaffeeValue::Function(f) = callee {
-            self.eval_userfn_call(cp, &f)
-        } else {
-            panic!("Called an uncallable value, eg. 3.14()");
+    pub(crate) fn call_native (&mut self, nf: &NativeMapping, rargs: Vec<KaffeeValue>, span: Span) -> KaffeeResult<KaffeeValue> {
+        if rargs.len() != nf.arg_count {
+            return Err(KaffeeError::new(ErrorKind::Argument,
+                format!("{} takes {} arguments, but {} were supplied", nf.name, nf.arg_count, rargs.len())))
         }
+
+        self.push_frame(&nf.name, span);
+        let res = (nf.func)(rargs, &mut self.vars);
+        self.pop_frame(res)
     }
 
-    fn eval_userfn_call (&mut self, cp: &CallProperties, fd: &FunctionDefinition) -> KaffeeValue {
-        self.vars.new_scope();
+    // Whether a Kaffee function can be called with this many arguments
+    pub(crate) fn check_call (&self, fd: &FunctionDefinition, arg_count: usize) -> KaffeeResult<()> {
+        if arg_count != fd.args.len() {
+            return Err(KaffeeError::new(ErrorKind::Argument,
+                format!("Function takes {} arguments, but {} were supplied", fd.args.len(), arg_count)))
+        }
 
-        // Allocate arguments to the block scope
-        for i in 0..fd.args.len() {
-            let val = self.resolve_node(&cp.args[i]);
-            self.vars.alloc_in_scope(&fd.args[i], val, false);
+        // Stop before the Rust stack overflows
+        if let Some(max) = self.limits.max_call_depth {
+            if self.call_stack.len() >= max {
+                return Err(KaffeeError::new(ErrorKind::StackOverflow,
+                    format!("Maximum call depth of {} exceeded", max)))
+            }
         }
+        Ok(())
+    }
 
-        let (_, ret_val) = self.eval_node(&ASTNode::BlockStatement(fd.body.clone()));
+    // Runs f, then lets go of anything it rooted, even if it failed
+    fn rooted<T> (&mut self, f: impl FnOnce(&mut Self) -> KaffeeResult<T>) -> KaffeeResult<T> {
+        let mark = self.vars.root_mark();
+        let res = f(self);
+        self.vars.unroot_to(mark);
+        res
+    }
 
-        self.vars.pop_scope();
-        // This collects the argument variables
-        // But breaks returned objects
-        // self.gc_collect();
+    // Resolves a list of nodes (arguments, array items etc.), rooting each
+    // value so that collections while resolving the rest can't free it.
+    // Use inside rooted().
+    fn resolve_rooted (&mut self, nodes: &[ASTNode]) -> KaffeeResult<Vec<KaffeeValue>> {
+        let mut values = vec![];
+        for node in nodes {
+            let val = self.resolve_node(node)?;
+            self.vars.root(&val);
+            values.push(val);
+        }
+        Ok(values)
+    }
 
-        ret_val
+    pub(crate) fn push_frame (&mut self, name: &str, span: Span) {
+        self.call_stack.push(StackFrame {
+            name: name.to_string(),
+            span
+        });
     }
 
-    fn assign_variable (&mut self, bin: &BinaryProperties) {
-        if bin.operator != "=" { unreachable!() }
+    // Leaves the current call. An error coming out of it gets the
+    // stack as it was at the innermost call it passed through.
+    pub(crate) fn pop_frame<T> (&mut self, res: KaffeeResult<T>) -> KaffeeResult<T> {
+        let res = res.map_err(|mut e| {
+            if e.trace.is_empty() {
+                e.trace = self.call_stack.iter().rev().cloned().collect();
+            }
+            e
+        });
+        self.call_stack.pop();
+        res
+    }
 
-        let (key_exists, val_idx) = self.resolve_assignment_target(bin.left.as_ref());
+    // Tail calls are made here in a loop once the function that made
+    // them has returned, so they don't use any more of the Rust stack
+    fn eval_userfn_call (&mut self, rargs: Vec<KaffeeValue>, fd: &FunctionDefinition, span: Span) -> KaffeeResult<KaffeeValue> {
+        let mut ret_val = self.run_userfn(rargs, fd, span)?;
+        while let Some(TailCall { func, args, span }) = self.tail_call.take() {
+            let res = self.check_call(&func, args.len())
+                .and_then(|_| self.run_userfn(args, &func, span));
+            ret_val = TailCall::pinned(res, span)?;
+        }
+        Ok(ret_val)
+    }
 
-        if !key_exists {
-            self.handle_insertion(bin);
-            return
+    fn run_userfn (&mut self, rargs: Vec<KaffeeValue>, fd: &FunctionDefinition, span: Span) -> KaffeeResult<KaffeeValue> {
+        self.push_frame(fd.display_name(), span);
+        self.vars.enter_env(fd.env.clone());
+        self.vars.new_scope();
+        let res = self.eval_userfn_body(rargs, fd);
+        self.vars.leave_env();
+        let ret_val = self.pop_frame(res)?;
+
+        // The argument variables and locals can be collected now.
+        // The returned value is rooted so it survives, and so
+        // is a tail call that's still to be made.
+        let pending = self.tail_call.take();
+        let mark = self.vars.root_mark();
+        self.vars.root(&ret_val);
+        if let Some(call) = &pending {
+            self.vars.root(&KaffeeValue::Function(call.func.clone()));
+            for arg in &call.args {
+                self.vars.root(arg);
+            }
         }
+        let res = self.gc_safepoint();
+        self.vars.unroot_to(mark);
+
+        res.map(|_| {
+            self.tail_call = pending;
+            ret_val
+        })
+    }
 
-        if self.vars.alloced[&val_idx].constant {
-            panic!("Assignment to constant value")
+    fn eval_userfn_body (&mut self, rargs: Vec<KaffeeValue>, fd: &FunctionDefinition) -> KaffeeResult<KaffeeValue> {
+        // Allocate arguments to the function's scope
+        for (name, val) in fd.args.iter().zip(rargs) {
+            self.vars.alloc_in_scope(name, None, val, false)?;
         }
 
-        let val = self.resolve_node(bin.right.as_ref());
-        self.vars.alloced.get_mut(&val_idx).unwrap().value = val;
+        // The body gets a block scope of its own, like any other block
+        self.vars.new_scope();
+        let res = self.eval_block(&fd.body);
+        self.vars.pop_scope();
+
+        let (_, ret_val) = res?;
+        Ok(ret_val)
     }
 
-    fn handle_insertion (&mut self, bin: &BinaryProperties) {
-        let pa = match bin.left.as_ref() {
-            ASTNode::PropertyAccess(x) => x,
-            _ => unreachable!()
-        };
+    fn assign_variable (&mut self, bin: &BinaryProperties) -> KaffeeResult<()> {
+        if bin.operator != "=" { unreachable!() }
 
-        // Can't do implicit assignment with an array
-        match self.resolve_node(pa.object.as_ref()) {
-            KaffeeValue::Object(_) => {},
-            _ => panic!("Attempted to access a non-existent key in an array")
-        }
+        // The value comes first. Resolving it can run code (and collections),
+        // so the target's index is only looked up once nothing else can run.
+        let val = self.resolve_node(bin.right.as_ref())?;
+
+        self.rooted(|this| {
+            this.vars.root(&val);
+            let target = bin.left.as_ref();
+            match &target.kind {
+                NodeKind::Identifier(id) => {
+                    this.vars.variable_index(id)
+                        .and_then(|idx| this.set_slot(idx, val))
+                        .map_err(|e| e.with_span(target.span))
+                },
+                // The object and key are only resolved once,
+                // even when the key's new
+                NodeKind::PropertyAccess(pa) => {
+                    let (object, key) = this.resolve_access(pa)?;
+                    this.set_property(object, key, val).map_err(|e| e.with_span(target.span))
+                },
+                _ => Err(KaffeeError::new(ErrorKind::Syntax,
+                    String::from("Can't assign to this type")).with_span(target.span))
+            }
+        })
+    }
 
-        let (exists, obj_idx) = self.resolve_assignment_target(pa.object.as_ref());
-        if !exists {
-            // TODO: Reword this error
-            panic!("Assignment with more than one level of non-existant key")
+    // Assigns to a variable's or property's heap slot
+    pub(crate) fn set_slot (&mut self, idx: ValueHandle, value: KaffeeValue) -> KaffeeResult<()> {
+        if self.vars.alloced[idx].constant {
+            return Err(KaffeeError::new(ErrorKind::ConstAssignment,
+                String::from("Assignment to constant value")))
         }
 
-        let key = self.resolve_node(pa.property.as_ref());
+        self.vars.set_value(idx, value);
+        Ok(())
+    }
 
-        let value = self.resolve_node(bin.right.as_ref());
+    // object[key] = value, adding the key if it's new
+    pub(crate) fn set_property (&mut self, object: KaffeeValue, key: KaffeeValue, value: KaffeeValue) -> KaffeeResult<()> {
+        if let Some(idx) = self.property_index(&object, &key)? {
+            return self.set_slot(idx, value)
+        }
 
-        self.vars.insert_into_object(key, value, obj_idx);
+        match object {
+            KaffeeValue::Object(obj) => match ObjectKey::from_value(&key) {
+                Some(key) => {
+                    self.vars.insert_into_object(key, value, obj);
+                    Ok(())
+                },
+                None => Err(KaffeeError::new(ErrorKind::Type,
+                    String::from("Functions can't be used as object keys")))
+            },
+            // Arrays can grow by one at a time, eg. arr[len(arr)] = x
+            KaffeeValue::Array(arr) => match variables::array_index(&key) {
+                Some(i) => self.vars.array_set(arr, i, value),
+                None => Err(KaffeeError::new(ErrorKind::Key,
+                    String::from("Arrays can only be indexed with whole numbers")))
+            },
+            _ => unreachable!()
+        }
     }
 
-    pub fn define_variable (&mut self, dcl: &DeclarationProperties) {
-        if let ASTNode::Identifier(id) = dcl.assignment.left.as_ref() {
-            let val = self.resolve_node(dcl.assignment.right.as_ref());
-            self.vars.alloc_in_scope(id, val, dcl.constant)
+    pub fn define_variable (&mut self, dcl: &DeclarationProperties) -> KaffeeResult<()> {
+        if let NodeKind::Identifier(id) = &dcl.assignment.left.kind {
+            let val = self.resolve_node(dcl.assignment.right.as_ref())?;
+            let slot = id.binding.map(|b| b.slot);
+            self.vars.alloc_in_scope(&id.name, slot, val, dcl.constant)
         } else {
-            panic!("Left side of a declaration isn't an identifier")
-        }
-    }
-
-    fn resolve_node (&mut self, node: &ASTNode) -> KaffeeValue {
-        match node {
-            ASTNode::String(st) => KaffeeValue::String(st.clone()),
-            ASTNode::Number(n) => KaffeeValue::Number(n.clone()),
-            ASTNode::Boolean(bl) => KaffeeValue::Boolean(bl.clone()),
-            ASTNode::Null => KaffeeValue::Null,
-            ASTNode::Identifier(id) => self.vars.resolve_identifier(id).clone(),
-            ASTNode::BinaryNode(bn) => self.resolve_binary(&bn),
-            ASTNode::ObjectLiteral(ov) => self.resolve_object_literal(ov),
-            ASTNode::PropertyAccess(pa) => self.resolve_property_access(&pa),
-            ASTNode::FunctionDefinition(fd) => self.ast_func_to_value(&fd),
-            ASTNode::FunctionCall(cp) => self.eval_call(&cp),
-            ASTNode::ArrayLiteral(items) => self.resolve_array_literal(&items),
+            Err(KaffeeError::new(ErrorKind::Syntax,
+                String::from("Left side of a declaration isn't an identifier")))
+        }
+    }
+
+    fn resolve_node (&mut self, node: &ASTNode) -> KaffeeResult<KaffeeValue> {
+        self.resolve_node_kind(node).map_err(|e| e.with_span(node.span))
+    }
+
+    // The arms return their results as they are, rather than using ?,
+    // since this is on the Rust stack once per level of Kaffee expression
+    fn resolve_node_kind (&mut self, node: &ASTNode) -> KaffeeResult<KaffeeValue> {
+        match &node.kind {
+            NodeKind::String(st) => Ok(KaffeeValue::String(st.clone())),
+            NodeKind::Number(n) => Ok(KaffeeValue::Number(*n)),
+            NodeKind::Boolean(bl) => Ok(KaffeeValue::Boolean(*bl)),
+            NodeKind::Null => Ok(KaffeeValue::Null),
+            NodeKind::Identifier(id) => self.vars.resolve_identifier(id).cloned(),
+            NodeKind::BinaryNode(bn) => self.resolve_binary(bn),
+            NodeKind::UnaryNode(un) => self.resolve_unary(un),
+            NodeKind::UpdateNode(up) => self.resolve_update(up),
+            NodeKind::ObjectLiteral(ov) => self.resolve_object_literal(ov),
+            NodeKind::PropertyAccess(pa) => self.resolve_property_access(pa),
+            NodeKind::FunctionDefinition(fd) => Ok(self.ast_func_to_value(fd)),
+            NodeKind::FunctionCall(cp) => self.eval_call(cp, node.span),
+            NodeKind::ArrayLiteral(items) => self.resolve_array_literal(items),
             _ => {
-                print_ast_node(node, 0);
-                panic!("Unresolvable ASTNode value")
+                Err(KaffeeError::new(ErrorKind::Syntax,
+                    String::from("Expected an expression, but found a statement")))
             }
         }
     }
 
-    fn resolve_array_literal (&mut self, items: &Vec<ASTNode>) -> KaffeeValue {
-        let mut idxs = vec![];
-        for i in items {
-            let val = self.resolve_node(i);
-            idxs.push(self.vars.alloc_value(val, false))
-        }
+    fn resolve_unary (&mut self, un: &UnaryProperties) -> KaffeeResult<KaffeeValue> {
+        let val = self.resolve_node(un.operand.as_ref())?;
+        operators::unary_handler(&un.operator, val)
+    }
 
-        KaffeeValue::Array(idxs)
+    fn resolve_array_literal (&mut self, items: &[ASTNode]) -> KaffeeResult<KaffeeValue> {
+        self.rooted(|this| {
+            let values = this.resolve_rooted(items)?;
+            Ok(this.vars.new_array(values))
+        })
     }
 
-    // Returns (key exists (for property access), alloc idx)
-    fn resolve_assignment_target (&mut self, node: &ASTNode) -> (bool, usize) {
-        match node {
-            ASTNode::Identifier(id) => (true, self.vars.find_variable_index(&id)),
-            ASTNode::PropertyAccess(pa) => self.lookup_property_access(pa),
+    // Returns the heap handle being assigned to,
+    // or None if it's an object key that doesn't exist
+    fn resolve_assignment_target (&mut self, node: &ASTNode) -> KaffeeResult<Option<ValueHandle>> {
+        let res = match &node.kind {
+            NodeKind::Identifier(id) => self.vars.variable_index(id).map(Some),
+            NodeKind::PropertyAccess(pa) => self.lookup_property_access(pa),
             _ => {
-                print_ast_node(node, 0);
-                panic!("Can't assign to this type")
+                Err(KaffeeError::new(ErrorKind::Syntax,
+                    String::from("Can't assign to this type")))
             }
-        }
+        };
+        res.map_err(|e| e.with_span(node.span))
     }
 
-    fn lookup_property_access (&mut self, pa: &AccessProperties) -> (bool, usize) {
+    // The object and key of object[key]
+    fn resolve_access (&mut self, pa: &AccessProperties) -> KaffeeResult<(KaffeeValue, KaffeeValue)> {
         // TODO: std_lib wrapper for prop. access on primitives like String for chars
-        let lft = self.resolve_node(pa.object.as_ref());
-        let key = self.resolve_node(pa.property.as_ref());
+        let lft = self.resolve_node(pa.object.as_ref())?;
+        // The object might be a temporary, eg. make()[key()]
+        let key = self.rooted(|this| {
+            this.vars.root(&lft);
+            this.resolve_node(pa.property.as_ref())
+        })?;
+        Ok((lft, key))
+    }
+
+    fn lookup_property_access (&mut self, pa: &AccessProperties) -> KaffeeResult<Option<ValueHandle>> {
+        let (lft, key) = self.resolve_access(pa)?;
+        self.property_index(&lft, &key)
+    }
 
-        if let KaffeeValue::Object(obj) = lft {
-            return self.vars.lookup_object_value_index(&obj, &key)
-        } else if let KaffeeValue::Array(items) = lft {
-            return self.vars.lookup_array_value_index(&items, &key)
+    // The heap slot of object[key], or None if it doesn't have that key
+    pub(crate) fn property_index (&mut self, object: &KaffeeValue, key: &KaffeeValue) -> KaffeeResult<Option<ValueHandle>> {
+        match object {
+            KaffeeValue::Object(obj) => Ok(self.vars.lookup_object_value_index(*obj, key)),
+            KaffeeValue::Array(arr) => Ok(self.vars.lookup_array_value_index(*arr, key)),
+            _ => Err(KaffeeError::new(ErrorKind::Type,
+                String::from("Property access isn't supported on that type")))
         }
+    }
 
-        panic!("Property access isn't supported on that type")
+    fn resolve_property_access (&mut self, pa: &AccessProperties) -> KaffeeResult<KaffeeValue> {
+        match self.lookup_property_access(pa)? {
+            Some(idx) => Ok(self.vars.alloced[idx].value.clone()),
+            None => Err(missing_key().with_span(pa.property.span))
+        }
+    }
+
+    fn resolve_object_literal (&mut self, ov: &ObjectLiteralProperties) -> KaffeeResult<KaffeeValue> {
+        self.rooted(|this| {
+            // Resolve the values before allocating anything,
+            // since resolving them might collect
+            let res_vals = this.resolve_rooted(&ov.values)?;
+            Ok(this.vars.new_object(ov.keys.iter().cloned().zip(res_vals)))
+        })
     }
 
-    fn resolve_property_access (&mut self, pa: &AccessProperties) -> KaffeeValue {
-        let (exists, idx) = self.lookup_property_access(pa);
+    // ++ and --, returning the new value for prefix and the old one for postfix
+    fn resolve_update (&mut self, up: &UpdateProperties) -> KaffeeResult<KaffeeValue> {
+        let idx = match self.resolve_assignment_target(up.target.as_ref())? {
+            Some(idx) => idx,
+            None => return Err(missing_key())
+        };
+        self.update_slot(idx, &up.operator, up.prefix)
+    }
 
-        if !exists {
-            panic!("Property access key doesn't exist")
+    pub(crate) fn update_slot (&mut self, idx: ValueHandle, operator: &str, prefix: bool) -> KaffeeResult<KaffeeValue> {
+        if self.vars.alloced[idx].constant {
+            return Err(KaffeeError::new(ErrorKind::ConstAssignment,
+                String::from("Assignment to constant value")))
         }
 
-        self.vars.alloced[&idx].value.clone()
-    }
+        let old = match self.vars.alloced[idx].value {
+            KaffeeValue::Number(n) => n,
+            _ => return Err(KaffeeError::new(ErrorKind::Type,
+                format!("\"{}\" can only be used on numbers", operator)))
+        };
+        let new = if operator == "++" { old + 1. } else { old - 1. };
 
+        self.vars.set_value(idx, KaffeeValue::Number(new));
+        Ok(KaffeeValue::Number(if prefix { new } else { old }))
+    }
 
-    fn resolve_object_literal (&mut self, ov: &ObjectLiteralProperties) -> KaffeeValue {
-        let mut keys = vec![];
-        let mut values = vec![];
+    fn resolve_binary (&mut self, bn: &BinaryProperties) -> KaffeeResult<KaffeeValue> {
+        let lft = self.resolve_node(bn.left.as_ref())?;
 
-        // Alloc the keys as Kaffee strings
-        for key in &ov.keys {
-            let idx = self.vars.alloc_value(KaffeeValue::String(key.clone()), true);
-            keys.push(idx);
+        // && and || only look at the right when the left doesn't
+        // decide it, and give back whichever side decided
+        match &bn.operator[..] {
+            "&&" if !operators::is_truthy(&lft) => return Ok(lft),
+            "||" if operators::is_truthy(&lft) => return Ok(lft),
+            "&&" | "||" => return self.resolve_node(bn.right.as_ref()),
+            _ => {}
         }
 
-        // Alloc the values
-        for val in &ov.values {
-            // Resolve the value
-            let res_val = self.resolve_node(val);
-            // Alloc the value
-            let idx = self.vars.alloc_value(res_val, false);
-            values.push(idx);
-        }
+        let rgt = self.rooted(|this| {
+            this.vars.root(&lft);
+            this.resolve_node(bn.right.as_ref())
+        })?;
 
-        KaffeeValue::Object(ObjectValue {
-            keys,
-            values
-        })
+        operators::operator_handler(lft, &bn.operator, rgt)
     }
+}
+
+// A statement that ran to the end, if it didn't fail
+fn no_break<T> (res: KaffeeResult<T>) -> KaffeeResult<(BreakType, KaffeeValue)> {
+    res.map(|_| (BreakType::None, KaffeeValue::Null))
+}
 
-    fn resolve_binary (&mut self, bn: &BinaryProperties) -> KaffeeValue {
-        let lft = self.resolve_node(bn.left.as_ref());
-        let rgt = self.resolve_node(bn.right.as_ref());
+pub(crate) fn missing_key () -> KaffeeError {
+    KaffeeError::new(ErrorKind::Key,
+        String::from("Property access key doesn't exist"))
+}
 
-        return operators::operator_handler(lft, &bn.operator, rgt);
+pub(crate) fn uncallable () -> KaffeeError {
+    KaffeeError::new(ErrorKind::Type,
+        String::from("Called an uncallable value, eg. 3.14()"))
+}
+
+fn uncatchable<T> (res: &KaffeeResult<T>) -> bool {
+    match res {
+        Err(e) => !e.catchable(),
+        Ok(_) => false
     }
 }
 
-pub fn new (code: String) -> Interpreter {
-    let mut ps = parser::new(code);
-    let ast = ps.generate_ast();
+pub fn new (code: String) -> KaffeeResult<Interpreter> {
+    let mut ps = parser::new(code.clone())?;
+    let ast = ps.generate_ast()?;
 
-    Interpreter {
+    let mut interp = Interpreter {
         ast,
-        vars: variables::new()
-    }
+        vars: variables::new(),
+        sources: vec![code],
+        call_stack: vec![],
+        limits: ExecutionLimits::default(),
+        steps: 0,
+        backend: Backend::TreeWalker,
+        optimize: true,
+        tail_call: None,
+        compiled: HashMap::new()
+    };
+    interp.load_globals();
+    Ok(interp)
 }
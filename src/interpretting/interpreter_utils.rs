@@ -1,48 +1,161 @@
 use crate::parsing::ast_utils::ASTNode;
-use crate::interpretting::variables::Variables;
+use crate::parsing::span::Span;
+use crate::interpretting::variables::{Variables, Scope};
+use crate::interpretting::arena::Handle;
+use crate::interpretting::ordered_map;
+use crate::interpretting::ordered_map::OrderedMap;
+use crate::interpretting::bytecode::Chunk;
+use crate::errors::KaffeeResult;
+use std::time::Instant;
+use std::rc::Rc;
 
-#[derive(Clone)]
+// Points at a value on the heap
+pub type ValueHandle = Handle<AllocedValue>;
+// Points at an array's items. Arrays are references, so every
+// copy of the same array value sees the same items.
+pub type ArrayHandle = Handle<Vec<ValueHandle>>;
+// Points at an object's keys and values, which are shared the same way
+pub type ObjectHandle = Handle<ObjectValue>;
+// Points at a scope's variables, which closures can hold onto
+pub type ScopeHandle = Handle<Scope>;
+
+#[derive(Clone, Debug)]
 pub struct AllocedValue {
     pub value: KaffeeValue,
     pub constant: bool
 }
 
+impl AllocedValue {
+    // Roughly how much memory the value takes up, for heap limits
+    pub fn approx_size (&self) -> usize {
+        std::mem::size_of::<AllocedValue>() + match &self.value {
+            KaffeeValue::String(st) => st.len(),
+            KaffeeValue::NativeFunction(nm) => nm.name.len(),
+            // The name, args and body are shared with the definition,
+            // only the scopes it closes over are its own
+            KaffeeValue::Function(f) => f.env.len() * std::mem::size_of::<ScopeHandle>(),
+            _ => 0
+        }
+    }
+}
+
+// Roughly how much memory an array's items take up
+pub fn array_size (items: &[ValueHandle]) -> usize {
+    std::mem::size_of::<Vec<ValueHandle>>() + std::mem::size_of_val(items)
+}
+
+// Each key is kept twice, once in order and once in the hash map
+pub fn object_size (obj: &ObjectValue) -> usize {
+    let entry = std::mem::size_of::<(ObjectKey, ValueHandle)>() + std::mem::size_of::<(ObjectKey, usize)>();
+    std::mem::size_of::<ObjectValue>() + obj.len() * entry + obj.key_bytes * 2
+}
+
+// Limits on how long a script can run for, for scripts that can't be trusted
+// to finish. Running out of steps or time stops the script with an
+// ExecutionLimit error that it can't catch.
+#[derive(Clone, Debug)]
+pub struct ExecutionLimits {
+    // How many steps (statements, loop iterations and calls) the
+    // interpreter can take in total. Both backends count the same steps,
+    // so they stop in the same place.
+    pub max_steps: Option<u64>,
+    // When to give up
+    pub deadline: Option<Instant>,
+    // How deep calls can go before a StackOverflow error. In the tree
+    // walker each level uses a few KB of Rust stack (a lot more in debug
+    // builds), so this needs to suit the stack of the thread running the
+    // interpreter. The default leaves room to spare on an ordinary 8MB
+    // stack, even in debug builds. The bytecode VM keeps its calls on
+    // the heap.
+    pub max_call_depth: Option<usize>
+}
+
+// How scripts get run. They both give the same results,
+// the bytecode VM just gets there quicker.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Backend {
+    // Walks the AST
+    TreeWalker,
+    // Compiles to bytecode first, then runs that on a stack machine
+    Bytecode
+}
+
+impl Default for ExecutionLimits {
+    fn default () -> ExecutionLimits {
+        ExecutionLimits {
+            max_steps: None,
+            deadline: None,
+            max_call_depth: Some(150)
+        }
+    }
+}
+
 // Used to determine how blocks were left
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum BreakType {
     None,
     Break,
     Continue,
     Return
-    // TODO: Exception
+}
+
+// A tail call that's waiting for its caller to finish returning,
+// so it can be made without the caller still on the Rust stack
+#[derive(Clone, Debug)]
+pub struct TailCall {
+    pub func: FunctionDefinition,
+    pub args: Vec<KaffeeValue>,
+    pub span: Span
+}
+
+impl TailCall {
+    // The caller's gone by the time a tail call's made,
+    // so errors from making it are pinned to the call
+    pub fn pinned<T> (res: KaffeeResult<T>, span: Span) -> KaffeeResult<T> {
+        res.map_err(|e| e.with_span(span))
+    }
 }
 
 // These also correspond to types
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum KaffeeValue {
     // Primitives
     Number(f64),
-    String(String),
+    // Immutable, so copies of a string can all share it
+    String(Rc<str>),
     Boolean(bool),
     Null,
     NativeFunction(NativeMapping),
 
     // Composed
-    Object(ObjectValue),
-    // Points into the alloc hashmap
-    Array(Vec<usize>),
+    Object(ObjectHandle),
+    Array(ArrayHandle),
     Function(FunctionDefinition)
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct FunctionDefinition {
-    pub args: Vec<String>,
-    pub body: Vec<ASTNode>
+    // Empty if it didn't have one
+    pub name: Rc<str>,
+    // Shared with the definition it was made from
+    pub args: Rc<Vec<String>>,
+    pub body: Rc<Vec<ASTNode>>,
+    // The scopestack the function was created in
+    pub env: Vec<ScopeHandle>,
+    // The body compiled for the bytecode VM, if it made the function
+    pub code: Option<Rc<Chunk>>
+}
+
+impl FunctionDefinition {
+    // For stack traces
+    pub fn display_name (&self) -> &str {
+        if self.name.is_empty() { "<anonymous>" } else { &self.name }
+    }
 }
 
-pub type NativeFuncSignature = fn(Vec<KaffeeValue>, &mut Variables) -> KaffeeValue;
+pub type NativeFuncSignature = fn(Vec<KaffeeValue>, &mut Variables) -> KaffeeResult<KaffeeValue>;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct NativeMapping {
     pub name: String,
     pub arg_count: usize,
@@ -56,9 +169,83 @@ impl PartialEq for NativeMapping {
     }
 }
 
-#[derive(Clone, PartialEq)]
+// An object's properties, in the order they were added
+#[derive(Clone, Debug)]
 pub struct ObjectValue {
-    pub keys: Vec<usize>,
-    // Indexes into alloced
-    pub values: Vec<usize>
+    // Handles into alloced
+    pub properties: OrderedMap<ObjectKey, ValueHandle>,
+    // How long its string keys are, for heap limits
+    pub key_bytes: usize
+}
+
+impl ObjectValue {
+    pub fn get (&self, key: &ObjectKey) -> Option<ValueHandle> {
+        self.properties.get(key).copied()
+    }
+
+    // Gives back the value the key had before, if it had one
+    pub fn insert (&mut self, key: ObjectKey, value: ValueHandle) -> Option<ValueHandle> {
+        if !self.properties.contains_key(&key) {
+            if let ObjectKey::String(st) = &key {
+                self.key_bytes += st.len()
+            }
+        }
+        self.properties.insert(key, value)
+    }
+
+    pub fn len (&self) -> usize {
+        self.properties.len()
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.properties.is_empty()
+    }
+}
+
+pub fn new_object_value () -> ObjectValue {
+    ObjectValue {
+        properties: ordered_map::new(),
+        key_bytes: 0
+    }
+}
+
+// Anything that can be an object key, in a form that can be hashed.
+// Two values are the same key if they're ==, so arrays and objects
+// are keys by identity.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ObjectKey {
+    String(Rc<str>),
+    // The bits of the f64
+    Number(u64),
+    Boolean(bool),
+    Null,
+    Array(ArrayHandle),
+    Object(ObjectHandle)
+}
+
+impl ObjectKey {
+    // Functions can't be keys
+    pub fn from_value (value: &KaffeeValue) -> Option<ObjectKey> {
+        Some(match value {
+            KaffeeValue::String(st) => ObjectKey::String(st.clone()),
+            // 0 == -0, so they need to be the same key
+            KaffeeValue::Number(n) => ObjectKey::Number(if *n == 0. { 0f64.to_bits() } else { n.to_bits() }),
+            KaffeeValue::Boolean(bl) => ObjectKey::Boolean(*bl),
+            KaffeeValue::Null => ObjectKey::Null,
+            KaffeeValue::Array(arr) => ObjectKey::Array(*arr),
+            KaffeeValue::Object(obj) => ObjectKey::Object(*obj),
+            KaffeeValue::Function(_) | KaffeeValue::NativeFunction(_) => return None
+        })
+    }
+
+    pub fn to_value (&self) -> KaffeeValue {
+        match self {
+            ObjectKey::String(st) => KaffeeValue::String(st.clone()),
+            ObjectKey::Number(bits) => KaffeeValue::Number(f64::from_bits(*bits)),
+            ObjectKey::Boolean(bl) => KaffeeValue::Boolean(*bl),
+            ObjectKey::Null => KaffeeValue::Null,
+            ObjectKey::Array(arr) => KaffeeValue::Array(*arr),
+            ObjectKey::Object(obj) => KaffeeValue::Object(*obj)
+        }
+    }
 }
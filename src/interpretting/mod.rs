@@ -2,3 +2,11 @@ pub mod interpreter;
 pub mod interpreter_utils;
 pub mod variables;
 pub mod garbage_collector;
+pub mod arena;
+pub mod heap_snapshot;
+pub mod ordered_map;
+pub mod bytecode;
+pub mod compiler;
+pub mod vm;
+pub mod resolver;
+pub mod optimizer;
@@ -0,0 +1,194 @@
+use crate::parsing::ast_utils::*;
+use crate::parsing::span::Span;
+use crate::interpretting::interpreter_utils::KaffeeValue;
+use crate::std_lib::operators;
+
+// Simplifies code before it runs: works out expressions that only use
+// literals (60 * 60 * 24), takes the branch of an if that can only go one
+// way, and drops code that can't be reached, like anything after a return.
+//
+// It runs after the resolver, so names in code it drops still have to
+// exist. Anything that would fail (1 / 0) is left alone to fail when it's
+// reached, with the same error as before.
+
+// Top-level statements are only ever replaced, not removed,
+// since eval gives back the value of the last one
+pub fn optimize (ast: &mut [ASTNode]) {
+    for node in ast {
+        statement(node, true);
+    }
+}
+
+// Simplifies a list of statements, leaving out ones that don't do
+// anything and everything after one that always leaves the block
+fn block (stmts: &mut Vec<ASTNode>) {
+    for node in stmts.iter_mut() {
+        statement(node, false);
+    }
+    stmts.retain(|node| !is_empty_block(node));
+    if let Some(end) = stmts.iter().position(always_leaves) {
+        stmts.truncate(end + 1);
+    }
+}
+
+// top_level is whether its value could be what eval gives back
+fn statement (node: &mut ASTNode, top_level: bool) {
+    let span = node.span;
+    match &mut node.kind {
+        NodeKind::BlockStatement(bs) => block(bs),
+        NodeKind::Declaration(dcl) => expression(&mut dcl.assignment.right),
+        NodeKind::Assignment(asn) => {
+            expression(&mut asn.left);
+            expression(&mut asn.right)
+        },
+        NodeKind::FunctionDefinition(fd) => function(fd),
+        NodeKind::IfStatement(ifs) => {
+            expression(&mut ifs.check_exp);
+            statement(&mut ifs.body, false);
+            if let Some(en) = &mut ifs.else_exp {
+                statement(en, false);
+            }
+
+            let check = match literal(&ifs.check_exp) {
+                Some(check) => check,
+                None => return
+            };
+            let branch = if operators::is_truthy(&check) {
+                Some(&mut ifs.body)
+            } else {
+                ifs.else_exp.as_mut()
+            };
+            // An if's value is null, but the branch's might not be
+            if top_level && branch.as_ref().is_some_and(|b| b.is_expression()) {
+                return
+            }
+            let taken = match branch {
+                Some(b) => std::mem::replace(b.as_mut(), empty_block(span)),
+                None => empty_block(span)
+            };
+            *node = taken;
+        },
+        NodeKind::WhileLoop(wp) => {
+            expression(&mut wp.check);
+            statement(&mut wp.body, false);
+            if let Some(check) = literal(&wp.check) {
+                if !operators::is_truthy(&check) {
+                    *node = empty_block(span);
+                }
+            }
+        },
+        NodeKind::TryStatement(tp) => {
+            statement(&mut tp.body, false);
+            if let Some(cb) = &mut tp.catch_body {
+                statement(cb, false);
+            }
+            if let Some(fb) = &mut tp.finally_body {
+                statement(fb, false);
+            }
+        },
+        NodeKind::ReturnStatement(exp) | NodeKind::ThrowStatement(exp) => expression(exp),
+        NodeKind::ContinueStatement | NodeKind::BreakStatement => {},
+        _ => expression(node)
+    }
+}
+
+fn expression (node: &mut ASTNode) {
+    let span = node.span;
+    match &mut node.kind {
+        NodeKind::BinaryNode(bn) => {
+            expression(&mut bn.left);
+            expression(&mut bn.right);
+            if let Some(folded) = fold_binary(bn, span) {
+                *node = folded;
+            }
+        },
+        NodeKind::UnaryNode(un) => {
+            expression(&mut un.operand);
+            let folded = literal(&un.operand)
+                .and_then(|val| operators::unary_handler(&un.operator, val).ok())
+                .and_then(|val| literal_node(val, span));
+            if let Some(folded) = folded {
+                *node = folded;
+            }
+        },
+        NodeKind::UpdateNode(up) => expression(&mut up.target),
+        NodeKind::ObjectLiteral(ov) => ov.values.iter_mut().for_each(expression),
+        NodeKind::PropertyAccess(pa) => {
+            expression(&mut pa.object);
+            expression(&mut pa.property)
+        },
+        NodeKind::FunctionDefinition(fd) => function(fd),
+        NodeKind::FunctionCall(cp) => {
+            expression(&mut cp.callee);
+            cp.args.iter_mut().for_each(expression)
+        },
+        NodeKind::ArrayLiteral(items) => items.iter_mut().for_each(expression),
+        _ => {}
+    }
+}
+
+fn function (fd: &mut FunctionDefinitionProperties) {
+    block(fd.body_mut())
+}
+
+// What a binary node comes to, if its operands are already known
+fn fold_binary (bn: &mut BinaryProperties, span: Span) -> Option<ASTNode> {
+    let left = literal(&bn.left)?;
+    match &bn.operator[..] {
+        // The left decides it, so the right never runs
+        "&&" if !operators::is_truthy(&left) => literal_node(left, span),
+        "||" if operators::is_truthy(&left) => literal_node(left, span),
+        // Otherwise it's whatever the right gives
+        "&&" | "||" => Some(std::mem::replace(bn.right.as_mut(), empty_block(span))),
+        _ => {
+            let right = literal(&bn.right)?;
+            operators::operator_handler(left, &bn.operator, right).ok()
+                .and_then(|val| literal_node(val, span))
+        }
+    }
+}
+
+fn literal (node: &ASTNode) -> Option<KaffeeValue> {
+    Some(match &node.kind {
+        NodeKind::String(st) => KaffeeValue::String(st.clone()),
+        NodeKind::Number(n) => KaffeeValue::Number(*n),
+        NodeKind::Boolean(bl) => KaffeeValue::Boolean(*bl),
+        NodeKind::Null => KaffeeValue::Null,
+        _ => return None
+    })
+}
+
+fn literal_node (value: KaffeeValue, span: Span) -> Option<ASTNode> {
+    let kind = match value {
+        KaffeeValue::String(st) => NodeKind::String(st),
+        KaffeeValue::Number(n) => NodeKind::Number(n),
+        KaffeeValue::Boolean(bl) => NodeKind::Boolean(bl),
+        KaffeeValue::Null => NodeKind::Null,
+        _ => return None
+    };
+    Some(ASTNode::new(kind, span))
+}
+
+// Stands in for a statement that doesn't do anything
+fn empty_block (span: Span) -> ASTNode {
+    ASTNode::new(NodeKind::BlockStatement(vec![]), span)
+}
+
+fn is_empty_block (node: &ASTNode) -> bool {
+    matches!(&node.kind, NodeKind::BlockStatement(bs) if bs.is_empty())
+}
+
+// Whether nothing after a statement in the same block can run
+fn always_leaves (node: &ASTNode) -> bool {
+    match &node.kind {
+        NodeKind::ReturnStatement(_) | NodeKind::ThrowStatement(_) |
+        NodeKind::BreakStatement | NodeKind::ContinueStatement => true,
+        // Blocks have already been cut short after whatever leaves them
+        NodeKind::BlockStatement(bs) => bs.last().is_some_and(always_leaves),
+        NodeKind::IfStatement(ifs) => match &ifs.else_exp {
+            Some(en) => always_leaves(&ifs.body) && always_leaves(en),
+            None => false
+        },
+        _ => false
+    }
+}
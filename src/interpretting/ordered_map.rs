@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// A hash map that remembers the order keys were added in, so objects
+// print and copy their keys in the order the script gave them.
+// Entries live in a Vec in that order, and the HashMap says where
+// each key's entry is.
+
+#[derive(Clone, Debug)]
+pub struct OrderedMap<K, V> {
+    entries: Vec<(K, V)>,
+    positions: HashMap<K, usize>
+}
+
+impl<K: Hash + Eq + Clone, V> OrderedMap<K, V> {
+    pub fn get (&self, key: &K) -> Option<&V> {
+        self.positions.get(key).map(|i| &self.entries[*i].1)
+    }
+
+    pub fn contains_key (&self, key: &K) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    // A new key goes on the end. An existing key keeps its place and
+    // gets the new value, and the old one is given back.
+    pub fn insert (&mut self, key: K, value: V) -> Option<V> {
+        if let Some(i) = self.positions.get(&key) {
+            return Some(std::mem::replace(&mut self.entries[*i].1, value))
+        }
+
+        self.positions.insert(key.clone(), self.entries.len());
+        self.entries.push((key, value));
+        None
+    }
+
+    pub fn len (&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // In the order they were added
+    pub fn iter (&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    pub fn values (&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, v)| v)
+    }
+}
+
+pub fn new<K, V> () -> OrderedMap<K, V> {
+    OrderedMap {
+        entries: vec![],
+        positions: HashMap::new()
+    }
+}
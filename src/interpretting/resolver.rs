@@ -0,0 +1,221 @@
+use crate::parsing::ast_utils::*;
+use crate::parsing::span::Span;
+use crate::interpretting::variables::Variables;
+use crate::errors::*;
+
+// Works out where every variable lives before the code runs, so reading
+// one is an index into a scope instead of a search through them by name.
+// It makes the same scopes the interpreter does: one per block, one for a
+// call's arguments and one around a catch block.
+//
+// A scope gives every name declared in it a slot up front, even ones
+// declared further down, since a function can be called after they are.
+// A read of a slot that's not been declared yet carries on by name to
+// the scopes further out, just like before there was a resolver.
+// Names that aren't declared anywhere in view are errors straight away.
+//
+// While it's there it also marks which calls are tail calls, ie. the
+// value of a return with nothing left to do in the function after it.
+
+struct Resolver {
+    // The names of each scope in view, innermost last
+    scopes: Vec<Vec<String>>,
+    // Whether a return here leaves its function straight away. Not at the
+    // top level, or in a try that still has a catch or finally to run.
+    tail_position: bool
+}
+
+// Resolves code that's about to run in the current scope (normally the
+// global one), reserving slots there for the variables it declares
+pub fn resolve (ast: &mut [ASTNode], vars: &mut Variables) -> KaffeeResult<()> {
+    let top = vars.scopestack.len() - 1;
+    for name in declared_names(ast) {
+        vars.scope_mut(top).reserve(&name);
+    }
+
+    let mut res = Resolver {
+        scopes: vars.scopestack.iter()
+            .map(|id| vars.scopes[*id].names.clone())
+            .collect(),
+        tail_position: false
+    };
+    for node in ast {
+        res.statement(node)?;
+    }
+    Ok(())
+}
+
+// The names a list of statements declares in the scope they run in.
+// Bodies that aren't blocks (if x let y = 1) don't get scopes of their own.
+fn declared_names (stmts: &[ASTNode]) -> Vec<String> {
+    let mut names = vec![];
+    for node in stmts {
+        add_declared_names(node, &mut names);
+    }
+    names
+}
+
+fn add_declared_names (node: &ASTNode, names: &mut Vec<String>) {
+    let name = match &node.kind {
+        NodeKind::Declaration(dcl) => match &dcl.assignment.left.kind {
+            NodeKind::Identifier(id) => &id.name[..],
+            _ => return
+        },
+        NodeKind::FunctionDefinition(fd) if !fd.name.is_empty() => &fd.name[..],
+        NodeKind::IfStatement(ifs) => {
+            add_declared_names(&ifs.body, names);
+            if let Some(en) = &ifs.else_exp {
+                add_declared_names(en, names);
+            }
+            return
+        },
+        NodeKind::WhileLoop(wp) => return add_declared_names(&wp.body, names),
+        NodeKind::TryStatement(tp) => {
+            add_declared_names(&tp.body, names);
+            if let Some(fb) = &tp.finally_body {
+                add_declared_names(fb, names);
+            }
+            return
+        },
+        _ => return
+    };
+
+    if !names.iter().any(|n| n == name) {
+        names.push(name.to_string())
+    }
+}
+
+impl Resolver {
+    // Finds the innermost scope with a slot for a name
+    fn lookup (&self, name: &str, span: Span) -> KaffeeResult<Binding> {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(slot) = scope.iter().position(|n| n == name) {
+                return Ok(Binding { depth, slot })
+            }
+        }
+        Err(KaffeeError::new(ErrorKind::Reference,
+            format!("Unresolved identifier \"{}\"", name)).with_span(span))
+    }
+
+    // The slot a name has in the innermost scope
+    fn own_slot (&self, name: &str) -> Option<usize> {
+        self.scopes.last().unwrap().iter().position(|n| n == name)
+    }
+
+    fn block (&mut self, stmts: &mut [ASTNode]) -> KaffeeResult<()> {
+        self.scopes.push(declared_names(stmts));
+        let res = stmts.iter_mut().try_for_each(|node| self.statement(node));
+        self.scopes.pop();
+        res
+    }
+
+    fn statement (&mut self, node: &mut ASTNode) -> KaffeeResult<()> {
+        match &mut node.kind {
+            NodeKind::BlockStatement(bs) => self.block(bs),
+            NodeKind::Declaration(dcl) => {
+                // The value is worked out before the variable exists
+                self.expression(&mut dcl.assignment.right)?;
+                if let NodeKind::Identifier(id) = &mut dcl.assignment.left.kind {
+                    id.binding = self.own_slot(&id.name).map(|slot| Binding { depth: 0, slot });
+                }
+                Ok(())
+            },
+            NodeKind::Assignment(asn) => {
+                self.expression(&mut asn.right)?;
+                self.expression(&mut asn.left)
+            },
+            NodeKind::FunctionDefinition(fd) => {
+                if !fd.name.is_empty() {
+                    fd.slot = self.own_slot(&fd.name);
+                }
+                self.function(fd)
+            },
+            NodeKind::IfStatement(ifs) => {
+                self.expression(&mut ifs.check_exp)?;
+                self.statement(&mut ifs.body)?;
+                match &mut ifs.else_exp {
+                    Some(en) => self.statement(en),
+                    None => Ok(())
+                }
+            },
+            NodeKind::ReturnStatement(exp) => {
+                if let NodeKind::FunctionCall(cp) = &mut exp.kind {
+                    cp.tail = self.tail_position;
+                }
+                self.expression(exp)
+            },
+            NodeKind::ThrowStatement(exp) => self.expression(exp),
+            NodeKind::WhileLoop(wp) => {
+                self.expression(&mut wp.check)?;
+                self.statement(&mut wp.body)
+            },
+            NodeKind::TryStatement(tp) => {
+                // Returns in the try block still have the catch or finally
+                // block to go through, and ones in the catch have the finally
+                let tail_position = self.tail_position;
+                self.tail_position = false;
+                let mut res = self.statement(&mut tp.body);
+                if let Some(cb) = &mut tp.catch_body {
+                    self.tail_position = tail_position && tp.finally_body.is_none();
+                    // The catch block is inside a scope with the caught value
+                    self.scopes.push(tp.catch_name.iter().cloned().collect());
+                    res = res.and_then(|_| self.statement(cb));
+                    self.scopes.pop();
+                }
+                self.tail_position = tail_position;
+                res?;
+
+                match &mut tp.finally_body {
+                    Some(fb) => self.statement(fb),
+                    None => Ok(())
+                }
+            },
+            NodeKind::ContinueStatement | NodeKind::BreakStatement => Ok(()),
+            // Expressions used as statements
+            _ => self.expression(node)
+        }
+    }
+
+    fn expression (&mut self, node: &mut ASTNode) -> KaffeeResult<()> {
+        match &mut node.kind {
+            NodeKind::Identifier(id) => {
+                id.binding = Some(self.lookup(&id.name, node.span)?);
+                Ok(())
+            },
+            NodeKind::BinaryNode(bn) => {
+                self.expression(&mut bn.left)?;
+                self.expression(&mut bn.right)
+            },
+            NodeKind::UnaryNode(un) => self.expression(&mut un.operand),
+            NodeKind::UpdateNode(up) => self.expression(&mut up.target),
+            NodeKind::ObjectLiteral(ov) => {
+                ov.values.iter_mut().try_for_each(|val| self.expression(val))
+            },
+            NodeKind::PropertyAccess(pa) => {
+                self.expression(&mut pa.object)?;
+                self.expression(&mut pa.property)
+            },
+            NodeKind::FunctionDefinition(fd) => self.function(fd),
+            NodeKind::FunctionCall(cp) => {
+                self.expression(&mut cp.callee)?;
+                cp.args.iter_mut().try_for_each(|arg| self.expression(arg))
+            },
+            NodeKind::ArrayLiteral(items) => {
+                items.iter_mut().try_for_each(|item| self.expression(item))
+            },
+            // Literals, and statements where an expression should
+            // be, which only fail once they're reached
+            _ => Ok(())
+        }
+    }
+
+    // Calls get a scope for their arguments, then the body gets a block scope
+    fn function (&mut self, fd: &mut FunctionDefinitionProperties) -> KaffeeResult<()> {
+        let tail_position = std::mem::replace(&mut self.tail_position, true);
+        self.scopes.push(fd.args.to_vec());
+        let res = self.block(fd.body_mut());
+        self.scopes.pop();
+        self.tail_position = tail_position;
+        res
+    }
+}
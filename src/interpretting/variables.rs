@@ -1,169 +1,411 @@
-use std::collections::HashMap;
 use crate::interpretting::interpreter_utils::*;
+use crate::parsing::ast_utils::{ASTNode, IdentifierProperties};
+use crate::interpretting::bytecode::Chunk;
+use crate::interpretting::arena;
+use crate::interpretting::arena::Arena;
+use crate::interpretting::garbage_collector;
+use crate::interpretting::garbage_collector::Collector;
+use crate::errors::*;
+use std::rc::Rc;
 
-// TODO: Objects are shallow copied
-//       This isn't right.
+// The bottom two frames of the scopestack
+pub const STD_LIB_SCOPE: usize = 0;
+pub const GLOBAL_SCOPE: usize = 1;
 
 pub struct Variables {
     // TODO: Constant should be in the scopestack?
-    pub alloced: HashMap<usize, AllocedValue>,
-    pub alloc_index: usize,
-    pub scopestack: Vec<HashMap<String, usize>>
+    pub alloced: Arena<AllocedValue>,
+    // The items of every array
+    pub arrays: Arena<Vec<ValueHandle>>,
+    // The keys and values of every object
+    pub objects: Arena<ObjectValue>,
+    // Every scope that's still reachable. Scopes live here rather
+    // than on the stack so closures can keep them after they're popped.
+    pub scopes: Arena<Scope>,
+    // The chain of scopes currently in view, innermost last
+    pub scopestack: Vec<ScopeHandle>,
+    // The callers' scopestacks while a function runs in its own environment
+    pub saved_stacks: Vec<Vec<ScopeHandle>>,
+    // Values that are only held by the interpreter for now (half-evaluated
+    // expressions, arguments, return values), which the GC mustn't free
+    pub temp_roots: Vec<KaffeeValue>,
+    // When and how to collect, and any collection in progress
+    pub gc: Collector,
+    // How much the heap is allowed to hold
+    pub limits: HeapLimits,
+    // Roughly how many bytes the heap's values take up
+    pub heap_bytes: usize
+}
+
+// Limits for running scripts that can't be trusted not to eat all the
+// memory. They're checked wherever the GC can run, so a script can go a
+// little over them between checks.
+#[derive(Clone, Debug, Default)]
+pub struct HeapLimits {
+    // How many values can be alive
+    pub max_values: Option<usize>,
+    // Roughly how many bytes they can take up
+    pub max_bytes: Option<usize>
+}
+
+// The variables declared in a block or function call. The resolver
+// decides which slot each one goes in, so reads can go straight there.
+#[derive(Default)]
+pub struct Scope {
+    // The name in each slot. The globals of code that's about to run
+    // have theirs reserved, other slots are named when they're declared.
+    pub names: Vec<String>,
+    // Each slot's value, once its declaration has run
+    pub values: Vec<Option<ValueHandle>>
+}
+
+impl Scope {
+    // The slot a name has, declared or not
+    pub fn slot_of (&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    pub fn get (&self, name: &str) -> Option<ValueHandle> {
+        self.slot_of(name).and_then(|slot| self.values[slot])
+    }
+
+    // Gives a name a slot, if it doesn't have one already
+    pub fn reserve (&mut self, name: &str) -> usize {
+        match self.slot_of(name) {
+            Some(slot) => slot,
+            None => {
+                self.names.push(name.to_string());
+                self.values.push(None);
+                self.names.len() - 1
+            }
+        }
+    }
+
+    fn set (&mut self, slot: usize, name: &str, idx: ValueHandle) {
+        if slot >= self.values.len() {
+            self.names.resize(slot + 1, String::new());
+            self.values.resize(slot + 1, None);
+        }
+        if self.names[slot] != name {
+            self.names[slot] = name.to_string();
+        }
+        self.values[slot] = Some(idx);
+    }
+
+    // The variables that have been declared
+    pub fn bindings (&self) -> impl Iterator<Item = (&String, ValueHandle)> {
+        self.names.iter().zip(&self.values)
+            .filter_map(|(name, idx)| idx.map(|idx| (name, idx)))
+    }
 }
 
 impl Variables {
-    pub fn resolve_identifier (&mut self, name: &String) -> &KaffeeValue {
-        let idx = self.find_variable_index(name);
+    pub fn resolve_identifier (&self, id: &IdentifierProperties) -> KaffeeResult<&KaffeeValue> {
+        let idx = self.variable_index(id)?;
 
-        &self.alloced[&idx].value
+        Ok(&self.alloced[idx].value)
+    }
+
+    // Resolved variables go straight to their slot. If it's not been
+    // declared yet, the name might still be declared further out
+    // (eg. reading a global before a local of the same name is declared).
+    pub fn variable_index (&self, id: &IdentifierProperties) -> KaffeeResult<ValueHandle> {
+        let mut outer = self.scopestack.len();
+        if let Some(b) = id.binding {
+            if let Some(depth) = outer.checked_sub(b.depth + 1) {
+                let scope = &self.scopes[self.scopestack[depth]];
+                if let Some(Some(idx)) = scope.values.get(b.slot) {
+                    return Ok(*idx)
+                }
+                outer = depth;
+            }
+        }
+        self.find_variable_index(&id.name, outer)
     }
 
-    pub fn find_variable_index (&mut self, name: &String) -> usize {
-        let max = self.scopestack.len() - 1;
-        for i in (0..=max).rev() {
-            let hm = &self.scopestack[i];
-            match hm.get(name) {
-                Some(idx) => return idx.clone(),
-                None => continue
+    // Searches the scopes below `outer` in the scopestack by name
+    pub fn find_variable_index (&self, name: &str, outer: usize) -> KaffeeResult<ValueHandle> {
+        for id in self.scopestack[..outer].iter().rev() {
+            if let Some(idx) = self.scopes[*id].get(name) {
+                return Ok(idx)
             }
         }
-        panic!("Unresolved identifier \"{}\"", name)
+        Err(KaffeeError::new(ErrorKind::Reference,
+            format!("Unresolved identifier \"{}\"", name)))
     }
 
     pub fn new_scope (&mut self) {
-        self.scopestack.push(HashMap::new())
+        let scope = self.scopes.insert(Scope::default());
+        self.scopestack.push(scope);
     }
 
+    // The scope itself sticks around until the GC finds nothing captures it
     pub fn pop_scope (&mut self) {
         self.scopestack.pop();
     }
 
-    pub fn alloc_in_scope (&mut self, identifier: &String, value: KaffeeValue, constant: bool) {
-        let top_scope = self.scopestack.len() - 1;
-        if self.scopestack[top_scope].contains_key(identifier) {
-            panic!("Attempt to shadow identifier \"{}\" within the same scope!
-(You declared a variable with a conflicting name)", identifier)
-        }
+    // Switches to a function's captured environment, remembering the caller's
+    pub fn enter_env (&mut self, env: Vec<ScopeHandle>) {
+        let caller = std::mem::replace(&mut self.scopestack, env);
+        self.saved_stacks.push(caller);
+    }
 
-        let idx = self.alloc_value(value, constant);
-        self.add_to_scope(identifier.clone(), idx);
+    pub fn leave_env (&mut self) {
+        self.scopestack = self.saved_stacks.pop().unwrap();
     }
 
-    pub fn add_to_scope (&mut self, identifier: String, alloc_index: usize) {
-        let idx = self.scopestack.len() - 1;
-        self.scopestack[idx].insert(identifier, alloc_index);
+    // Keeps a value alive through collections until it's unrooted
+    pub fn root (&mut self, value: &KaffeeValue) {
+        self.temp_roots.push(value.clone())
     }
 
-    pub fn alloc_value (&mut self, value: KaffeeValue, constant: bool) -> usize {
-        self.alloced.insert(self.alloc_index, AllocedValue {
-            value,
-            constant
-        });
-        self.alloc_index += 1;
-
-        self.alloc_index - 1
-    }
-
-    pub fn print_allocced (&self) {
-        for (i, v) in &self.alloced {
-            println!("{} - {}", i,
-                match &v.value {
-                    KaffeeValue::Number(n) => {
-                        format!("Number: {}", n)
-                    },
-                    KaffeeValue::String(s) => {
-                        format!("String: \"{}\"", s)
-                    },
-                    KaffeeValue::NativeFunction(nm) => {
-                        format!("NativeFunction: \"{}\" - {} args", nm.name, nm.arg_count)
-                    },
-                    KaffeeValue::Function(f) => {
-                        format!("Function: {} args, {} body nodes", f.args.len(), f.body.len())
-                    },
-                    KaffeeValue::Object(ov) => {
-                        let mut st = format!("Object:");
-                        for i in 0..ov.keys.len() {
-                            st = format!("{}\n    - {} - {}", st, ov.keys[i], ov.values[i])
-                        }
-                        st
-                    },
-                    KaffeeValue::Array(it) => {
-                        let mut st = format!("Array:");
-                        for i in it {
-                            st = format!("{}\n    - {}", st, i);
-                        }
-                        st
-                    },
-                    KaffeeValue::Boolean(bl) => {
-                        format!("Boolean: {}", bl)
-                    },
-                    KaffeeValue::Null => {
-                        format!("Null")
-                    }
-                }
-            )
+    // Roots are released in bulk. Take a mark before rooting things,
+    // then unroot back to it once they're stored somewhere reachable.
+    pub fn root_mark (&self) -> usize {
+        self.temp_roots.len()
+    }
+
+    pub fn unroot_to (&mut self, mark: usize) {
+        self.temp_roots.truncate(mark)
+    }
+
+    // Looks up the scope at a position in the current stack
+    pub fn scope (&self, depth: usize) -> &Scope {
+        &self.scopes[self.scopestack[depth]]
+    }
+
+    pub fn scope_mut (&mut self, depth: usize) -> &mut Scope {
+        let id = self.scopestack[depth];
+        &mut self.scopes[id]
+    }
+
+    // Declares a variable in the innermost scope, in the slot the resolver
+    // gave it. Without one it gets the slot its name has, or a new one.
+    pub fn alloc_in_scope (&mut self, identifier: &str, slot: Option<usize>, value: KaffeeValue, constant: bool) -> KaffeeResult<()> {
+        let top_scope = self.scopestack.len() - 1;
+        let slot = match slot {
+            Some(slot) => slot,
+            None => self.scope_mut(top_scope).reserve(identifier)
+        };
+
+        if let Some(Some(_)) = self.scope(top_scope).values.get(slot) {
+            return Err(KaffeeError::new(ErrorKind::Redeclaration,
+                format!("Attempt to shadow identifier \"{}\" within the same scope!
+(You declared a variable with a conflicting name)", identifier)))
         }
+
+        let idx = self.alloc_value(value, constant);
+        self.scope_mut(top_scope).set(slot, identifier, idx);
+        Ok(())
     }
 
-    pub fn print_scopestack (&self) {
-        for i in 0..self.scopestack.len() {
-            let scope = &self.scopestack[i];
-            println!("Scope frame {}:", i);
-            for (ident, idx) in scope {
-                println!(" - \"{}\" - {}", ident, idx);
+    // Looks a name up in the user and std_lib global scopes
+    pub fn get_global (&self, name: &str) -> Option<KaffeeValue> {
+        for i in (STD_LIB_SCOPE..=GLOBAL_SCOPE).rev() {
+            if let Some(idx) = self.scope(i).get(name) {
+                return Some(self.alloced[idx].value.clone())
             }
         }
+        None
     }
 
-    pub fn lookup_object_value_index (&self, obj: &ObjectValue, kv: &KaffeeValue) -> (bool, usize) {
-        for i in 0..obj.keys.len() {
-            let idx = obj.keys[i];
-            let key = &self.alloced[&idx].value;
+    // Defines a user global, or overwrites it if it already exists
+    pub fn set_global (&mut self, name: &str, value: KaffeeValue) {
+        self.set_in_scope(GLOBAL_SCOPE, name, value, false)
+    }
 
-            if key == kv {
-                return (true, obj.values[i])
-            }
+    pub fn set_in_scope (&mut self, scope: usize, name: &str, value: KaffeeValue, constant: bool) {
+        if let Some(idx) = self.scope(scope).get(name) {
+            self.set_value(idx, value);
+            self.alloced[idx].constant = constant;
+            return
         }
-        (false, 0)
+
+        let idx = self.alloc_value(value, constant);
+        let scope = self.scope_mut(scope);
+        let slot = scope.reserve(name);
+        scope.set(slot, name, idx);
+    }
+
+    // Allocates each item, for array literals and the host. Allocating
+    // never collects, so they can all go in at once.
+    pub fn new_array (&mut self, items: Vec<KaffeeValue>) -> KaffeeValue {
+        let idxs = items.into_iter()
+            .map(|item| self.alloc_value(item, false))
+            .collect();
+        KaffeeValue::Array(self.alloc_array(idxs))
+    }
+
+    pub fn alloc_array (&mut self, items: Vec<ValueHandle>) -> ArrayHandle {
+        self.heap_bytes += array_size(&items);
+        let arr = self.arrays.insert(items);
+        self.gc.on_alloc_array(arr);
+        arr
+    }
+
+    // Copies of the array's items, eg. for a native function to read
+    pub fn array_items (&self, arr: ArrayHandle) -> Vec<KaffeeValue> {
+        self.arrays[arr].iter()
+            .map(|idx| self.alloced[*idx].value.clone())
+            .collect()
+    }
+
+    // Copies of the object's keys and values, in the order they were added
+    pub fn object_entries (&self, obj: ObjectHandle) -> Vec<(ObjectKey, KaffeeValue)> {
+        self.objects[obj].properties.iter()
+            .map(|(key, val)| (key.clone(), self.alloced[*val].value.clone()))
+            .collect()
+    }
+
+    // Array items always go in new slots, so the write barrier doesn't need
+    // to know about them: new values are marked as soon as they're made
+    pub fn array_push (&mut self, arr: ArrayHandle, value: KaffeeValue) {
+        let len = self.arrays[arr].len();
+        self.array_insert(arr, len, value)
     }
 
-    pub fn lookup_array_value_index (&self, arr: &Vec<usize>, kv: &KaffeeValue) -> (bool, usize) {
-        if let KaffeeValue::Number(n) = kv {
-            // Can't cast a negative num to usize
-            if n < &0. { return (false, 0) }
-            // Can't use a non-integer to index array
-            if n % 1. != 0. { return (false, 0) }
+    pub fn array_insert (&mut self, arr: ArrayHandle, index: usize, value: KaffeeValue) {
+        let idx = self.alloc_value(value, false);
+        self.arrays[arr].insert(index, idx);
+        self.heap_bytes += std::mem::size_of::<ValueHandle>();
+    }
 
-            let idx = n.clone() as usize;
-            if idx >= arr.len() { return (false, 0) }
+    // Takes an item out, giving back its value
+    pub fn array_remove (&mut self, arr: ArrayHandle, index: usize) -> KaffeeValue {
+        let idx = self.arrays[arr].remove(index);
+        self.heap_bytes -= std::mem::size_of::<ValueHandle>();
+        self.alloced[idx].value.clone()
+    }
 
-            return (true, arr[idx])
+    // Sets an item, or adds one if the index is just past the end
+    pub fn array_set (&mut self, arr: ArrayHandle, index: usize, value: KaffeeValue) -> KaffeeResult<()> {
+        let len = self.arrays[arr].len();
+        if index < len {
+            let idx = self.arrays[arr][index];
+            self.set_value(idx, value);
+        } else if index == len {
+            self.array_push(arr, value);
+        } else {
+            return Err(KaffeeError::new(ErrorKind::Key,
+                format!("Index {} is past the end of an array of length {}", index, len)))
         }
+        Ok(())
+    }
 
-        (false, 0)
+    // Allocates each value, for object literals and the host. A key
+    // that's given twice keeps its first place and last value.
+    pub fn new_object<K: Into<Rc<str>>> (&mut self, entries: impl IntoIterator<Item = (K, KaffeeValue)>) -> KaffeeValue {
+        let mut obj = new_object_value();
+        for (key, value) in entries {
+            obj.insert(ObjectKey::String(key.into()), self.alloc_value(value, false));
+        }
+        KaffeeValue::Object(self.alloc_object(obj))
     }
 
-    pub fn insert_into_object (&mut self, key: KaffeeValue, value: KaffeeValue, obj_idx: usize) {
-        // Alloc
-        let ki = self.alloc_value(key, false);
-        let vi = self.alloc_value(value, false);
+    // A function made by running its definition. Closures see
+    // the scopes they were written in, not the caller's.
+    pub fn new_function (&self, name: Rc<str>, args: Rc<Vec<String>>, body: Rc<Vec<ASTNode>>, code: Option<Rc<Chunk>>) -> KaffeeValue {
+        KaffeeValue::Function(FunctionDefinition {
+            name,
+            args,
+            body,
+            env: self.scopestack.clone(),
+            code
+        })
+    }
+
+    pub fn alloc_object (&mut self, obj: ObjectValue) -> ObjectHandle {
+        self.heap_bytes += object_size(&obj);
+        let handle = self.objects.insert(obj);
+        self.gc.on_alloc_object(handle);
+        handle
+    }
 
-        let obj_val = self.alloced.get_mut(&obj_idx).unwrap();
-        let obj = match &mut obj_val.value {
-            KaffeeValue::Object(x) => x,
-            _ => unreachable!()
+    pub fn alloc_value (&mut self, value: KaffeeValue, constant: bool) -> ValueHandle {
+        let alloced = AllocedValue {
+            value,
+            constant
         };
+        self.heap_bytes += alloced.approx_size();
+
+        let idx = self.alloced.insert(alloced);
+        self.gc.on_alloc(idx);
+        idx
+    }
+
+    // How many values, arrays and objects are alive
+    pub fn heap_len (&self) -> usize {
+        self.alloced.len() + self.arrays.len() + self.objects.len()
+    }
+
+    pub fn over_limits (&self) -> bool {
+        let too_many = match self.limits.max_values {
+            Some(max) => self.heap_len() > max,
+            None => false
+        };
+        let too_big = match self.limits.max_bytes {
+            Some(max) => self.heap_bytes > max,
+            None => false
+        };
+        too_many || too_big
+    }
+
+    // Overwrites a value on the heap. Writes should go through here
+    // so an incremental collection sees what the new value points to.
+    pub fn set_value (&mut self, idx: ValueHandle, value: KaffeeValue) {
+        self.gc.write_barrier(&value);
+        let alloced = &mut self.alloced[idx];
+        self.heap_bytes -= alloced.approx_size();
+        alloced.value = value;
+        self.heap_bytes += alloced.approx_size();
+    }
+
+    pub fn lookup_object_value_index (&self, obj: ObjectHandle, kv: &KaffeeValue) -> Option<ValueHandle> {
+        ObjectKey::from_value(kv).and_then(|key| self.objects[obj].get(&key))
+    }
+
+    pub fn lookup_array_value_index (&self, arr: ArrayHandle, kv: &KaffeeValue) -> Option<ValueHandle> {
+        array_index(kv).and_then(|i| self.arrays[arr].get(i).copied())
+    }
+
+    // Like array items, values always go in new slots. Keys are kept in
+    // the object itself, so ones that point into the heap go through the
+    // write barrier.
+    pub fn insert_into_object (&mut self, key: ObjectKey, value: KaffeeValue, obj: ObjectHandle) {
+        self.gc.write_barrier(&key.to_value());
+        let vi = self.alloc_value(value, false);
+
+        let obj = &mut self.objects[obj];
+        self.heap_bytes -= object_size(obj);
+        obj.insert(key, vi);
+        self.heap_bytes += object_size(obj);
+    }
+}
+
+// Turns a key into an array index, if it can be one
+pub fn array_index (kv: &KaffeeValue) -> Option<usize> {
+    if let KaffeeValue::Number(n) = kv {
+        // Can't cast a negative num to usize
+        if n < &0. { return None }
+        // Can't use a non-integer to index array
+        if n % 1. != 0. { return None }
 
-        // Add the mapping
-        obj.keys.push(ki);
-        obj.values.push(vi);
+        return Some(*n as usize)
     }
+    None
 }
 
 pub fn new () -> Variables {
     Variables {
-        alloced: HashMap::new(),
-        alloc_index: 0,
-        scopestack: vec![]
+        alloced: arena::new(),
+        arrays: arena::new(),
+        objects: arena::new(),
+        scopes: arena::new(),
+        scopestack: vec![],
+        saved_stacks: vec![],
+        temp_roots: vec![],
+        gc: garbage_collector::new(),
+        limits: HeapLimits::default(),
+        heap_bytes: 0
     }
 }
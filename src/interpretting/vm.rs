@@ -0,0 +1,522 @@
+use crate::interpretting::interpreter;
+use crate::interpretting::interpreter::Interpreter;
+use crate::interpretting::interpreter_utils::*;
+use crate::interpretting::bytecode::*;
+use crate::std_lib::operators;
+use crate::parsing::span::Span;
+use crate::errors::*;
+use std::rc::Rc;
+
+// Runs the compiler's bytecode on a stack machine. Everything apart from
+// the running itself (variables, the heap, calls, errors) is shared with
+// the tree walker, so they give the same results. Kaffee calls don't
+// recurse in Rust, each one is just another frame.
+//
+// The stack is the GC's temp roots, so nothing on it can be collected.
+
+struct Frame {
+    chunk: Rc<Chunk>,
+    ip: usize,
+    // Where its part of the stack starts
+    stack_base: usize,
+    // How many scopes were in view when it started
+    scope_base: usize,
+    // The try and finally blocks it's inside, innermost last
+    handlers: Vec<Handler>,
+    // Calls have a caller's environment to go back to. The
+    // bottom frame is a top-level statement instead.
+    is_call: bool
+}
+
+enum Handler {
+    Try {
+        catch: Option<usize>,
+        finally: Option<usize>,
+        // The stack height and scopes to go back to
+        stack: usize,
+        scopes: usize
+    },
+    // A finally block that's running. The value that's waiting to be
+    // returned or thrown is on the stack at this height, and what to do
+    // after the block is at this index in pending.
+    Finally { stack: usize, pending: usize }
+}
+
+// What to carry on with after a finally block
+enum Completion {
+    Normal,
+    Return,
+    Leave(LeaveInfo),
+    Throw(KaffeeError)
+}
+
+struct Vm<'a> {
+    interp: &'a mut Interpreter,
+    frames: Vec<Frame>,
+    pending: Vec<Completion>
+}
+
+// Runs a top-level statement, giving back what it returned
+pub fn run (interp: &mut Interpreter, chunk: Chunk) -> KaffeeResult<KaffeeValue> {
+    let bottom = Frame {
+        chunk: Rc::new(chunk),
+        ip: 0,
+        stack_base: interp.vars.temp_roots.len(),
+        scope_base: interp.vars.scopestack.len(),
+        handlers: vec![],
+        is_call: false
+    };
+
+    let mut vm = Vm {
+        interp,
+        frames: vec![bottom],
+        pending: vec![]
+    };
+    loop {
+        match vm.execute() {
+            Ok(value) => return Ok(value),
+            // Carries on from a catch or finally block, if there is one
+            Err(e) => vm.unwind(e)?
+        }
+    }
+}
+
+impl<'a> Vm<'a> {
+    fn execute (&mut self) -> KaffeeResult<KaffeeValue> {
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let op = frame.chunk.code[frame.ip];
+            frame.ip += 1;
+
+            match self.step(op) {
+                Ok(None) => {},
+                Ok(Some(value)) => return Ok(value),
+                Err(e) => {
+                    let frame = self.frames.last().unwrap();
+                    return Err(e.with_span(frame.chunk.spans[frame.ip - 1]))
+                }
+            }
+        }
+    }
+
+    fn frame (&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn push (&mut self, value: KaffeeValue) {
+        self.interp.vars.temp_roots.push(value)
+    }
+
+    fn pop (&mut self) -> KaffeeValue {
+        self.interp.vars.temp_roots.pop().unwrap()
+    }
+
+    // The top n values, in the order they were pushed
+    fn pop_n (&mut self, n: u32) -> Vec<KaffeeValue> {
+        let stack = &mut self.interp.vars.temp_roots;
+        stack.split_off(stack.len() - n as usize)
+    }
+
+    fn peek (&self) -> &KaffeeValue {
+        self.interp.vars.temp_roots.last().unwrap()
+    }
+
+    fn name (&self, idx: u32) -> &Rc<str> {
+        &self.frames.last().unwrap().chunk.strings[idx as usize]
+    }
+
+    fn span (&self) -> Span {
+        let frame = self.frames.last().unwrap();
+        frame.chunk.spans[frame.ip - 1]
+    }
+
+    // Runs one op. Gives back a value once the top-level statement returns.
+    fn step (&mut self, op: Op) -> KaffeeResult<Option<KaffeeValue>> {
+        match op {
+            Op::Number(n) => self.push(KaffeeValue::Number(n)),
+            Op::String(idx) => {
+                let st = self.name(idx).clone();
+                self.push(KaffeeValue::String(st))
+            },
+            Op::Boolean(bl) => self.push(KaffeeValue::Boolean(bl)),
+            Op::Null => self.push(KaffeeValue::Null),
+            Op::Function(idx) => {
+                let proto = self.frame().chunk.functions[idx as usize].clone();
+                let func = self.interp.vars.new_function(proto.name.clone(), proto.args.clone(),
+                    proto.body.clone(), Some(proto.chunk.clone()));
+                self.push(func)
+            },
+            Op::Array(count) => {
+                let items = self.pop_n(count);
+                let arr = self.interp.vars.new_array(items);
+                self.push(arr)
+            },
+            Op::Object(idx) => {
+                let frame = self.frames.last().unwrap();
+                let keys = frame.chunk.objects[idx as usize].clone();
+                let values = self.pop_n(keys.len() as u32);
+                let obj = self.interp.vars.new_object(keys.into_iter().zip(values));
+                self.push(obj)
+            },
+
+            Op::Get(idx) => {
+                let id = &self.frames.last().unwrap().chunk.variables[idx as usize];
+                let val = self.interp.vars.resolve_identifier(id)?.clone();
+                self.push(val)
+            },
+            Op::Set(idx) => {
+                let val = self.pop();
+                let id = &self.frames.last().unwrap().chunk.variables[idx as usize];
+                let slot = self.interp.vars.variable_index(id)?;
+                self.interp.set_slot(slot, val)?
+            },
+            Op::Declare { variable, constant } => {
+                let val = self.pop();
+                let id = &self.frames.last().unwrap().chunk.variables[variable as usize];
+                let slot = id.binding.map(|b| b.slot);
+                self.interp.vars.alloc_in_scope(&id.name, slot, val, constant)?
+            },
+            Op::Update { variable, increment, prefix } => {
+                let id = &self.frames.last().unwrap().chunk.variables[variable as usize];
+                let slot = self.interp.vars.variable_index(id)?;
+                let res = self.interp.update_slot(slot, update_operator(increment), prefix)?;
+                self.push(res)
+            },
+
+            Op::GetProperty(idx) => {
+                let key = self.pop();
+                let object = self.pop();
+                match self.interp.property_index(&object, &key)? {
+                    Some(slot) => {
+                        let val = self.interp.vars.alloced[slot].value.clone();
+                        self.push(val)
+                    },
+                    None => {
+                        let span = self.frame().chunk.part_spans[idx as usize];
+                        return Err(interpreter::missing_key().with_span(span))
+                    }
+                }
+            },
+            Op::SetProperty => {
+                let key = self.pop();
+                let object = self.pop();
+                let val = self.pop();
+                self.interp.set_property(object, key, val)?
+            },
+            Op::UpdateProperty { increment, prefix } => {
+                let key = self.pop();
+                let object = self.pop();
+                let slot = match self.interp.property_index(&object, &key)? {
+                    Some(slot) => slot,
+                    None => return Err(interpreter::missing_key())
+                };
+                let res = self.interp.update_slot(slot, update_operator(increment), prefix)?;
+                self.push(res)
+            },
+
+            Op::Binary(op) => {
+                let rgt = self.pop();
+                let lft = self.pop();
+                let res = operators::binary(lft, op, rgt)?;
+                self.push(res)
+            },
+            Op::Not => self.unary("!")?,
+            Op::Negate => self.unary("-")?,
+            Op::Plus => self.unary("+")?,
+            Op::Pop => { self.pop(); },
+
+            Op::Jump(target) => self.frame().ip = target as usize,
+            Op::JumpIfFalse(target) => {
+                if !operators::is_truthy(&self.pop()) {
+                    self.frame().ip = target as usize
+                }
+            },
+            Op::And(target) => {
+                if operators::is_truthy(self.peek()) {
+                    self.pop();
+                } else {
+                    self.frame().ip = target as usize
+                }
+            },
+            Op::Or(target) => {
+                if operators::is_truthy(self.peek()) {
+                    self.frame().ip = target as usize
+                } else {
+                    self.pop();
+                }
+            },
+            Op::Loop(target) => {
+                self.interp.gc_safepoint()?;
+                self.frame().ip = target as usize
+            },
+
+            Op::EnterScope => self.interp.vars.new_scope(),
+            Op::LeaveScope => self.interp.vars.pop_scope(),
+            Op::Safepoint => self.interp.gc_safepoint()?,
+            Op::Tick => self.interp.tick()?,
+
+            Op::Call { args, callee } => self.call(args, callee)?,
+            Op::TailCall { args, callee } => self.tail_call(args, callee)?,
+            Op::Return => {
+                let val = self.pop();
+                return self.leave_function(val)
+            },
+            Op::Throw => {
+                let val = self.pop();
+                return Err(self.interp.thrown_error(val))
+            },
+            Op::Fail(idx) => {
+                return Err(KaffeeError::new(ErrorKind::Syntax, self.name(idx).to_string()))
+            },
+
+            Op::Try(idx) => {
+                let info = self.frame().chunk.tries[idx as usize];
+                let handler = Handler::Try {
+                    catch: info.catch,
+                    finally: info.finally,
+                    stack: self.interp.vars.temp_roots.len(),
+                    scopes: self.interp.vars.scopestack.len()
+                };
+                self.frame().handlers.push(handler)
+            },
+            Op::EndTry => { self.frame().handlers.pop(); },
+            Op::Finally => {
+                let stack = self.interp.vars.temp_roots.len();
+                let after = self.frame().ip;
+                self.run_finally(after, stack, KaffeeValue::Null, Completion::Normal)
+            },
+            Op::EndFinally => {
+                self.frame().handlers.pop();
+                let val = self.pop();
+                match self.pending.pop().unwrap() {
+                    Completion::Normal => {},
+                    Completion::Return => return self.leave_function(val),
+                    Completion::Leave(info) => self.leave(info),
+                    Completion::Throw(e) => return Err(e)
+                }
+            },
+            Op::Leave(idx) => {
+                let info = self.frame().chunk.leaves[idx as usize];
+                self.leave(info)
+            }
+        }
+        Ok(None)
+    }
+
+    fn unary (&mut self, op: &str) -> KaffeeResult<()> {
+        let val = self.pop();
+        let res = operators::unary_handler(op, val)?;
+        self.push(res);
+        Ok(())
+    }
+
+    fn call (&mut self, count: u32, callee_span: u32) -> KaffeeResult<()> {
+        let span = self.span();
+        let args = self.pop_n(count);
+        let callee = self.pop();
+
+        let fd = match callee {
+            KaffeeValue::NativeFunction(nf) => {
+                let res = self.interp.call_native(&nf, args, span)?;
+                self.push(res);
+                return Ok(())
+            },
+            KaffeeValue::Function(fd) => fd,
+            _ => {
+                let span = self.frame().chunk.part_spans[callee_span as usize];
+                return Err(interpreter::uncallable().with_span(span))
+            }
+        };
+        self.enter_function(fd, args, span)
+    }
+
+    // Calls a Kaffee function in place of the one that's running,
+    // so a function can keep tail calling without the frames piling
+    // up. Anything else is an ordinary call, and the Return after it
+    // hands back what it gave.
+    fn tail_call (&mut self, count: u32, callee_span: u32) -> KaffeeResult<()> {
+        let stack = &self.interp.vars.temp_roots;
+        let callee = &stack[stack.len() - 1 - count as usize];
+        let frame = self.frames.last().unwrap();
+        // The resolver leaves out calls inside try blocks, but one can
+        // still be inside a finally block, which it just leaves like Return
+        let in_try = frame.handlers.iter().any(|h| matches!(h, Handler::Try { .. }));
+        if !matches!(callee, KaffeeValue::Function(_)) || !frame.is_call || in_try {
+            return self.call(count, callee_span)
+        }
+
+        let span = self.span();
+        let args = self.pop_n(count);
+        let fd = match self.pop() {
+            KaffeeValue::Function(fd) => fd,
+            _ => unreachable!()
+        };
+
+        // Allocating the arguments never collects, so the callee
+        // and arguments are safe off the stack until they're in scope
+        while let Some(Handler::Finally { stack, pending }) = self.frame().handlers.pop() {
+            self.drop_finally(stack, pending)
+        }
+        let frame = self.frames.pop().unwrap();
+        self.interp.vars.temp_roots.truncate(frame.stack_base);
+        self.interp.vars.leave_env();
+        self.interp.pop_frame(Ok(()))?;
+
+        let res = self.enter_function(fd, args, span);
+        TailCall::pinned(res, span)
+    }
+
+    fn enter_function (&mut self, fd: FunctionDefinition, args: Vec<KaffeeValue>, span: Span) -> KaffeeResult<()> {
+        self.interp.check_call(&fd, args.len())?;
+
+        let chunk = self.interp.function_code(&fd, span);
+
+        self.interp.push_frame(fd.display_name(), span);
+        self.interp.vars.enter_env(fd.env);
+        self.interp.vars.new_scope();
+        for (name, val) in fd.args.iter().zip(args) {
+            if let Err(e) = self.interp.vars.alloc_in_scope(name, None, val, false) {
+                self.interp.vars.leave_env();
+                return self.interp.pop_frame(Err(e))
+            }
+        }
+
+        self.frames.push(Frame {
+            chunk,
+            ip: 0,
+            stack_base: self.interp.vars.temp_roots.len(),
+            scope_base: self.interp.vars.scopestack.len(),
+            handlers: vec![],
+            is_call: true
+        });
+        Ok(())
+    }
+
+    // Returns from the current frame, once any finally
+    // blocks it's inside have run
+    fn leave_function (&mut self, val: KaffeeValue) -> KaffeeResult<Option<KaffeeValue>> {
+        while let Some(handler) = self.frame().handlers.pop() {
+            match handler {
+                Handler::Try { finally: Some(finally), stack, scopes, .. } => {
+                    self.interp.vars.scopestack.truncate(scopes);
+                    self.run_finally(finally, stack, val, Completion::Return);
+                    return Ok(None)
+                },
+                Handler::Try { .. } => {},
+                Handler::Finally { stack, pending } => self.drop_finally(stack, pending)
+            }
+        }
+
+        let frame = self.frames.pop().unwrap();
+        self.interp.vars.temp_roots.truncate(frame.stack_base);
+        if !frame.is_call {
+            self.interp.vars.scopestack.truncate(frame.scope_base);
+            return Ok(Some(val))
+        }
+
+        self.interp.vars.leave_env();
+        self.interp.pop_frame(Ok(()))?;
+
+        // Only the returned value (back on the stack) has
+        // to survive, not the function's variables
+        self.push(val);
+        self.interp.gc_safepoint()?;
+        Ok(None)
+    }
+
+    // break and continue, running any finally blocks they leave first
+    fn leave (&mut self, info: LeaveInfo) {
+        while self.frame().handlers.len() > info.handlers {
+            match self.frame().handlers.pop().unwrap() {
+                Handler::Try { finally: Some(finally), stack, scopes, .. } => {
+                    self.interp.vars.scopestack.truncate(scopes);
+                    self.run_finally(finally, stack, KaffeeValue::Null, Completion::Leave(info));
+                    return
+                },
+                Handler::Try { .. } => {},
+                Handler::Finally { stack, pending } => self.drop_finally(stack, pending)
+            }
+        }
+
+        let frame = self.frames.last_mut().unwrap();
+        self.interp.vars.scopestack.truncate(frame.scope_base + info.scopes);
+        frame.ip = info.target;
+    }
+
+    // Jumps into a finally block, with the value that's waiting
+    // to be returned or thrown kept on the stack
+    fn run_finally (&mut self, finally: usize, stack: usize, val: KaffeeValue, after: Completion) {
+        self.interp.vars.temp_roots.truncate(stack);
+        self.push(val);
+        self.pending.push(after);
+
+        let pending = self.pending.len() - 1;
+        let frame = self.frame();
+        frame.handlers.push(Handler::Finally { stack, pending });
+        frame.ip = finally;
+    }
+
+    // Forgets a finally block that's being left early, along
+    // with whatever was going to happen after it
+    fn drop_finally (&mut self, stack: usize, pending: usize) {
+        self.pending.truncate(pending);
+        self.interp.vars.temp_roots.truncate(stack);
+    }
+
+    // Finds the catch or finally block for an error, leaving any frames
+    // that don't have one. Fails if nothing's left to catch it.
+    fn unwind (&mut self, mut err: KaffeeError) -> KaffeeResult<()> {
+        loop {
+            let catchable = err.catchable();
+            while let Some(handler) = self.frame().handlers.pop() {
+                if !catchable { continue }
+
+                match handler {
+                    Handler::Try { catch, finally, stack, scopes } => {
+                        self.interp.vars.temp_roots.truncate(stack);
+                        self.interp.vars.scopestack.truncate(scopes);
+
+                        if let Some(catch) = catch {
+                            // Kept for just the finally block, in case the catch block fails
+                            if finally.is_some() {
+                                let handler = Handler::Try { catch: None, finally, stack, scopes };
+                                self.frame().handlers.push(handler);
+                            }
+
+                            let caught = self.interp.error_to_value(err);
+                            self.push(caught);
+                            self.frame().ip = catch;
+                            return Ok(())
+                        }
+                        if let Some(finally) = finally {
+                            let val = match &err.value {
+                                Some(val) => *val.clone(),
+                                None => KaffeeValue::Null
+                            };
+                            self.run_finally(finally, stack, val, Completion::Throw(err));
+                            return Ok(())
+                        }
+                    },
+                    Handler::Finally { stack, pending } => self.drop_finally(stack, pending)
+                }
+            }
+
+            let frame = self.frames.pop().unwrap();
+            self.interp.vars.temp_roots.truncate(frame.stack_base);
+            if !frame.is_call {
+                self.interp.vars.scopestack.truncate(frame.scope_base);
+                return Err(err)
+            }
+
+            self.interp.vars.leave_env();
+            err = match self.interp.pop_frame::<()>(Err(err)) {
+                Err(e) => e,
+                Ok(_) => unreachable!()
+            };
+        }
+    }
+}
+
+fn update_operator (increment: bool) -> &'static str {
+    if increment { "++" } else { "--" }
+}
@@ -0,0 +1,41 @@
+//! Kaffee as a library, for running scripts from inside another
+//! Rust program.
+//!
+//! ```
+//! use kaffee::{interpreter, KaffeeValue, KaffeeResult, KaffeeError, ErrorKind, Variables};
+//!
+//! fn double (args: Vec<KaffeeValue>, _: &mut Variables) -> KaffeeResult<KaffeeValue> {
+//!     match args[0] {
+//!         KaffeeValue::Number(n) => Ok(KaffeeValue::Number(n * 2.)),
+//!         _ => Err(KaffeeError::new(ErrorKind::Type, String::from("double() takes a number")))
+//!     }
+//! }
+//!
+//! # fn main () -> KaffeeResult<()> {
+//! let mut interp = interpreter::new(String::from("let x = double(limit)"))?;
+//! interp.register_function("double", 1, double);
+//! interp.vars.set_global("limit", KaffeeValue::Number(21.));
+//! interp.run()?;
+//!
+//! assert!(interp.vars.get_global("x") == Some(KaffeeValue::Number(42.)));
+//! assert!(interp.eval(String::from("x + 1"))? == KaffeeValue::Number(43.));
+//!
+//! // Script errors come back as values rather than panics
+//! let err = interp.eval(String::from("double(\"a\")")).unwrap_err();
+//! assert_eq!(err.kind, ErrorKind::Type);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod errors;
+pub mod parsing;
+pub mod interpretting;
+pub mod std_lib;
+
+pub use interpretting::interpreter;
+pub use interpretting::interpreter::Interpreter;
+pub use interpretting::interpreter_utils::{KaffeeValue, NativeFuncSignature, NativeMapping, ExecutionLimits, Backend};
+pub use interpretting::variables::{Variables, HeapLimits};
+pub use interpretting::garbage_collector::GcSettings;
+pub use interpretting::heap_snapshot::HeapSnapshot;
+pub use errors::{KaffeeError, KaffeeResult, ErrorKind, StackFrame};
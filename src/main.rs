@@ -1,16 +1,221 @@
-mod parsing;
-mod interpretting;
-mod std_lib;
-use interpretting::interpreter;
-use parsing::printer::print_ast;
-
-fn main() {
-    let code = String::from("
-        println(\"What's your name?\")
-        let name = input()
-        println(\"Your name is \" + name + \", that's a nice name.\")
-    ");
-    let mut interp = interpreter::new(code);
-    print_ast(&interp.ast);
-    interp.run();
+mod repl;
+use kaffee::interpreter;
+use kaffee::Interpreter;
+use kaffee::KaffeeValue;
+use kaffee::Backend;
+use kaffee::parsing::printer::print_ast;
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+use std::time::Instant;
+
+const USAGE: &str = "Usage: kaffee [options] [script.kf | -] [script args...]
+
+Runs a Kaffee script. Pass - as the script to read it from stdin.
+Any arguments after the script are available to it as the `args` array.
+Without a script, starts an interactive REPL.
+
+Options:
+    --print-ast         Print the parsed AST before running
+    --print-heap        Print the allocated values and scopes after running
+    --heap-json <file>  Write a snapshot of the heap as JSON after running
+    --heap-dot <file>   Write the heap's object graph for Graphviz after running
+    --time              Print the execution time after running
+    --vm                Compile to bytecode and run that, which is faster
+    --no-optimize       Run the code as written, without constant folding etc.
+    -h, --help          Print this message";
+
+struct Options {
+    print_ast: bool,
+    print_heap: bool,
+    heap_json: Option<String>,
+    heap_dot: Option<String>,
+    time: bool,
+    backend: Backend,
+    optimize: bool,
+    // None starts the REPL
+    script: Option<String>,
+    script_args: Vec<String>
+}
+
+// Options come before the script path, everything after
+// it belongs to the script
+fn parse_args (args: Vec<String>) -> Result<Options, String> {
+    let mut print_ast = false;
+    let mut print_heap = false;
+    let mut heap_json = None;
+    let mut heap_dot = None;
+    let mut time = false;
+    let mut backend = Backend::TreeWalker;
+    let mut optimize = true;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match &arg[..] {
+            "--print-ast" => print_ast = true,
+            "--print-heap" => print_heap = true,
+            "--heap-json" => heap_json = Some(option_value(&arg, iter.next())?),
+            "--heap-dot" => heap_dot = Some(option_value(&arg, iter.next())?),
+            "--time" => time = true,
+            "--vm" => backend = Backend::Bytecode,
+            "--no-optimize" => optimize = false,
+            "-h" | "--help" => return Err(String::new()),
+            _ => {
+                if arg.starts_with("--") {
+                    return Err(format!("Unknown option \"{}\"", arg))
+                }
+
+                return Ok(Options {
+                    print_ast,
+                    print_heap,
+                    heap_json,
+                    heap_dot,
+                    time,
+                    backend,
+                    optimize,
+                    script: Some(arg),
+                    script_args: iter.collect()
+                })
+            }
+        }
+    }
+
+    Ok(Options {
+        print_ast,
+        print_heap,
+        heap_json,
+        heap_dot,
+        time,
+        backend,
+        optimize,
+        script: None,
+        script_args: vec![]
+    })
+}
+
+fn option_value (option: &str, value: Option<String>) -> Result<String, String> {
+    match value {
+        Some(v) => Ok(v),
+        None => Err(format!("\"{}\" needs a file to write to", option))
+    }
+}
+
+fn read_script (path: &str) -> io::Result<String> {
+    if path == "-" {
+        let mut code = String::new();
+        io::stdin().read_to_string(&mut code)?;
+        Ok(code)
+    } else {
+        fs::read_to_string(path)
+    }
+}
+
+// Errors come back already formatted with the code they point at
+fn run_script (code: String, opts: &Options) -> Result<(), String> {
+    let mut interp = match interpreter::new(code.clone()) {
+        Ok(i) => i,
+        Err(e) => return Err(e.render(&code))
+    };
+    interp.backend = opts.backend;
+    interp.optimize = opts.optimize;
+    interp.limits.max_call_depth = Some(MAX_CALL_DEPTH);
+
+    let args = opts.script_args.iter()
+        .map(|a| KaffeeValue::String(a.as_str().into()))
+        .collect();
+    let args = interp.vars.new_array(args);
+    interp.vars.set_global("args", args);
+
+    if opts.print_ast {
+        print_ast(&interp.ast);
+        println!();
+    }
+
+    let now = Instant::now();
+    let res = interp.run();
+
+    if opts.time {
+        println!("\nProgram execution time: {}ms", now.elapsed().as_millis());
+    }
+
+    // Still worth looking at the heap when the script failed,
+    // but its error is the one to report
+    let saved = dump_heap(&interp, opts);
+    res.map_err(|e| interp.render_error(&e))?;
+    saved
+}
+
+fn dump_heap (interp: &Interpreter, opts: &Options) -> Result<(), String> {
+    if !opts.print_heap && opts.heap_json.is_none() && opts.heap_dot.is_none() {
+        return Ok(())
+    }
+
+    let snapshot = interp.heap_snapshot();
+    if opts.print_heap {
+        println!("\n{}", snapshot);
+    }
+    if let Some(path) = &opts.heap_json {
+        write_output(path, &snapshot.to_json())?;
+    }
+    if let Some(path) = &opts.heap_dot {
+        write_output(path, &snapshot.to_dot())?;
+    }
+    Ok(())
+}
+
+fn write_output (path: &str, contents: &str) -> Result<(), String> {
+    fs::write(path, contents).map_err(|e| format!("Couldn't write \"{}\": {}", path, e))
+}
+
+// Each call in a script goes a few Rust frames deep, so the interpreter
+// gets a bigger stack than the main thread's, and can go deeper than
+// the default call depth (which suits an ordinary 8MB stack)
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+pub const MAX_CALL_DEPTH: usize = 1000;
+
+fn main () {
+    let runner = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run_cli)
+        .expect("Couldn't start the interpreter thread");
+
+    if runner.join().is_err() {
+        process::exit(101)
+    }
+}
+
+fn run_cli () {
+    let opts = match parse_args(env::args().skip(1).collect()) {
+        Ok(o) => o,
+        Err(msg) => {
+            if msg.is_empty() {
+                println!("{}", USAGE);
+                process::exit(0)
+            }
+            eprintln!("{}\n\n{}", msg, USAGE);
+            process::exit(2)
+        }
+    };
+
+    let script = match &opts.script {
+        Some(s) => s,
+        None => {
+            repl::start(opts.print_ast, opts.backend, opts.optimize);
+            return
+        }
+    };
+
+    let code = match read_script(script) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Couldn't read \"{}\": {}", script, e);
+            process::exit(2)
+        }
+    };
+
+    if let Err(e) = run_script(code, &opts) {
+        eprintln!("{}", e);
+        process::exit(1)
+    }
 }
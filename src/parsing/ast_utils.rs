@@ -1,8 +1,19 @@
-#[derive(Clone, PartialEq)]
-pub enum ASTNode {
-    String(String),
+use crate::parsing::span::Span;
+use std::rc::Rc;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ASTNode {
+    pub kind: NodeKind,
+    // Where the node came from in the code, for errors
+    pub span: Span
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum NodeKind {
+    // Shared with every value the literal makes
+    String(Rc<str>),
     Number(f64),
-    Identifier(String),
+    Identifier(IdentifierProperties),
     Boolean(bool),
     Null,
     ObjectLiteral(ObjectLiteralProperties),
@@ -10,6 +21,8 @@ pub enum ASTNode {
     Assignment(BinaryProperties),
     Declaration(DeclarationProperties),
     BinaryNode(BinaryProperties),
+    UnaryNode(UnaryProperties),
+    UpdateNode(UpdateProperties),
     FunctionCall(CallProperties),
     FunctionDefinition(FunctionDefinitionProperties),
     PropertyAccess(AccessProperties),
@@ -18,56 +31,144 @@ pub enum ASTNode {
     WhileLoop(WhileProperties),
     ContinueStatement,
     BreakStatement,
-    ArrayLiteral(Vec<ASTNode>)
+    ArrayLiteral(Vec<ASTNode>),
+    TryStatement(TryProperties),
+    ThrowStatement(Box<ASTNode>)
+}
+
+impl ASTNode {
+    pub fn new (kind: NodeKind, span: Span) -> ASTNode {
+        ASTNode { kind, span }
+    }
+
+    // A variable that hasn't been resolved yet
+    pub fn identifier (name: String, span: Span) -> ASTNode {
+        ASTNode::new(NodeKind::Identifier(IdentifierProperties {
+            name,
+            binding: None
+        }), span)
+    }
+
+    // Expressions produce a value, everything else is
+    // only run for its effects
+    pub fn is_expression (&self) -> bool {
+        match &self.kind {
+            NodeKind::FunctionDefinition(fd) => fd.name.is_empty(),
+            NodeKind::String(_) | NodeKind::Number(_) | NodeKind::Identifier(_) |
+            NodeKind::Boolean(_) | NodeKind::Null | NodeKind::ObjectLiteral(_) |
+            NodeKind::BinaryNode(_) | NodeKind::UnaryNode(_) |
+            NodeKind::UpdateNode(_) | NodeKind::FunctionCall(_) |
+            NodeKind::PropertyAccess(_) | NodeKind::ArrayLiteral(_) => true,
+            _ => false
+        }
+    }
+}
+
+// A variable, by name. The resolver works out where it lives before
+// the program runs, so reading it doesn't need a search by name.
+#[derive(Clone, PartialEq, Debug)]
+pub struct IdentifierProperties {
+    pub name: String,
+    pub binding: Option<Binding>
+}
+
+// Where a resolved variable is: how many scopes out from the
+// innermost one, and which slot of that scope
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Binding {
+    pub depth: usize,
+    pub slot: usize
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct WhileProperties {
     pub check: Box<ASTNode>,
     pub body: Box<ASTNode>
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
+pub struct TryProperties {
+    pub body: Box<ASTNode>,
+    // catch (name) - the name is optional, but catch and
+    // finally can't both be missing
+    pub catch_name: Option<String>,
+    pub catch_body: Option<Box<ASTNode>>,
+    pub finally_body: Option<Box<ASTNode>>
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub struct FunctionDefinitionProperties {
-    pub name: String,
-    pub args: Vec<String>,
+    // The name, args and body are shared with every
+    // function value made from the definition
+    pub name: Rc<str>,
+    pub args: Rc<Vec<String>>,
     // TODO: Make this a normal Box<ASTNode>
-    pub body: Vec<ASTNode>
+    pub body: Rc<Vec<ASTNode>>,
+    // The slot a named function is declared in, once resolved
+    pub slot: Option<usize>
+}
+
+impl FunctionDefinitionProperties {
+    // For passes that change the body before it runs. It's only copied
+    // if something else is sharing it already.
+    pub fn body_mut (&mut self) -> &mut Vec<ASTNode> {
+        Rc::make_mut(&mut self.body)
+    }
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct IfProperties {
     pub check_exp: Box<ASTNode>,
     pub body: Box<ASTNode>,
     pub else_exp: Option<Box<ASTNode>>
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct ObjectLiteralProperties {
-    pub keys: Vec<String>,
+    pub keys: Vec<Rc<str>>,
     pub values: Vec<ASTNode>
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct BinaryProperties {
     pub left: Box<ASTNode>,
     pub operator: String,
     pub right: Box<ASTNode>
 }
 
-#[derive(Clone, PartialEq)]
+// -x, +x and !x
+#[derive(Clone, PartialEq, Debug)]
+pub struct UnaryProperties {
+    pub operator: String,
+    pub operand: Box<ASTNode>
+}
+
+// ++x, x++, --x and x--
+#[derive(Clone, PartialEq, Debug)]
+pub struct UpdateProperties {
+    pub operator: String,
+    // ++x gives the new value, x++ gives the old one
+    pub prefix: bool,
+    // An identifier or property access
+    pub target: Box<ASTNode>
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub struct DeclarationProperties {
     pub constant: bool,
     pub assignment: BinaryProperties
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct CallProperties {
     pub callee: Box<ASTNode>,
-    pub args: Vec<ASTNode>
+    pub args: Vec<ASTNode>,
+    // Whether it's the last thing its function does (return f(x)), so the
+    // caller can be done with before it's made. Set by the resolver.
+    pub tail: bool
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct AccessProperties {
     pub object: Box<ASTNode>,
     pub property: Box<ASTNode>
@@ -1,7 +1,11 @@
+use crate::parsing::span::*;
+
 pub struct CharStream {
     pub code: Vec<char>,
     pub index: usize,
-    pub eof: bool
+    pub eof: bool,
+    // Position of the next char to be read
+    pub position: Position
 }
 
 impl CharStream {
@@ -12,10 +16,18 @@ impl CharStream {
     pub fn read (&mut self) -> char {
         let c = self.code[self.index];
 
+        if !self.eof {
+            if c == '\n' {
+                self.position.line += 1;
+                self.position.col = 1;
+            } else {
+                self.position.col += 1;
+            }
+        }
+
         if self.index >= self.code.len() - 1 {
-            self.eof = true;
             // Continually reads the final char once eof
-            self.index -= 1;
+            self.eof = true;
         } else {
             self.index += 1;
         }
@@ -25,9 +37,13 @@ impl CharStream {
 }
 
 pub fn new (code: String) -> CharStream {
+    let code: Vec<char> = code.chars().collect();
+    // Empty programs are eof before they start
+    let eof = code.is_empty();
     CharStream {
-        code: code.chars().collect(),
+        code,
         index: 0,
-        eof: false
+        eof,
+        position: start_position()
     }
 }
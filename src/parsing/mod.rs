@@ -5,3 +5,4 @@ pub mod parser;
 pub mod ast_utils;
 pub mod parser_helpers;
 pub mod printer;
+pub mod span;
@@ -2,7 +2,9 @@ use crate::parsing::tokens::*;
 use crate::parsing::tokeniser;
 use crate::parsing::tokeniser::Tokeniser;
 use crate::parsing::ast_utils::*;
-use crate::parsing::printer::print_token;
+use crate::parsing::span::Span;
+use crate::errors::*;
+use std::rc::Rc;
 
 // Parser does not act like a stream, it
 // constructs the AST in one go
@@ -14,52 +16,59 @@ pub struct Parser {
 //       Little methods like expect_punctuation()
 //       are in parser_helpers.rs
 impl Parser {
-    fn parse_atom (&mut self, accept_statements: bool) -> ASTNode {
-        let t = self.tokens.read();
+    fn parse_atom (&mut self, accept_statements: bool) -> KaffeeResult<ASTNode> {
+        let start = self.tokens.current_span;
+        let t = self.tokens.read()?;
 
         if let Token::Punctuation(pnc) = t {
             // Bracketed expressions
             if pnc == '(' {
-                let exp = self.parse_component(true, 0);
-                self.expect_punctuation(')');
-                return exp;
+                let exp = self.parse_component(true, 0)?;
+                self.expect_punctuation(')')?;
+                return Ok(exp);
             }
 
             // Array literal
             if pnc == '[' {
-                let exp = self.parse_array_literal();
-                self.expect_punctuation(']');
-                return exp;
+                let exp = self.parse_array_literal()?;
+                self.expect_punctuation(']')?;
+                return Ok(self.node(exp, start));
             }
         }
 
         match t {
             Token::String(st) => {
-                return ASTNode::String(st)
+                return Ok(self.node(NodeKind::String(st.into()), start))
             },
             Token::Number(n) => {
-                return ASTNode::Number(n)
+                return Ok(self.node(NodeKind::Number(n), start))
             },
             Token::Identifier(id) => {
-                return ASTNode::Identifier(id)
+                return Ok(ASTNode::identifier(id, start.to(self.tokens.last_span)))
             },
             Token::Boolean(bl) => {
-                return ASTNode::Boolean(bl)
+                return Ok(self.node(NodeKind::Boolean(bl), start))
             },
             Token::Null => {
-                return ASTNode::Null
+                return Ok(self.node(NodeKind::Null, start))
             },
             _ => {}
         }
 
+        // -x, !x, ++x etc.
+        if let Token::Operator(op) = &t {
+            if is_unary_operator(op) || is_update_operator(op) {
+                let prefixed = self.parse_prefix_operator(op.clone())?;
+                return Ok(self.node(prefixed, start))
+            }
+        }
+
         // Some keywords are ok for expressions
         // without being statements
         if let Token::Keyword(kw) = &t {
-            match &kw[..] {
-                "fn" => {
-                    return self.parse_function_definition()
-                },
-                _ => {}
+            if &kw[..] == "fn" {
+                let fd = self.parse_function_definition()?;
+                return Ok(self.node(fd, start))
             }
         }
 
@@ -69,20 +78,22 @@ impl Parser {
 
             if let Token::Punctuation(pnc) = t {
                 if pnc == '{' {
-                    return self.parse_object_literal();
+                    let obj = self.parse_object_literal()?;
+                    return Ok(self.node(obj, start));
                 }
             }
 
             // If we've got here and we're not using a dictionary,
             // we're in trouble
-            panic!("Used a statement where they are not allowed")
+            return self.syntax_error(format!("Expected an expression, found {}", t.describe()), start)
         }
 
-        self.parse_statement(t)
+        let stmt = self.parse_statement(t, start)?;
+        Ok(self.node(stmt, start))
     }
 
-    fn parse_statement (&mut self, t: Token) -> ASTNode {
-        if let Token::Keyword(kw) = t {
+    fn parse_statement (&mut self, t: Token, start: Span) -> KaffeeResult<NodeKind> {
+        if let Token::Keyword(kw) = &t {
             let kwstr = &kw[..];
             match kwstr {
                 "let" => {
@@ -98,18 +109,25 @@ impl Parser {
                     return self.parse_return_statement()
                 },
                 "continue" => {
-                    return ASTNode::ContinueStatement
+                    return Ok(NodeKind::ContinueStatement)
                 },
                 "break" => {
-                    return ASTNode::BreakStatement
+                    return Ok(NodeKind::BreakStatement)
                 },
                 "while" => {
                     return self.parse_while_loop()
                 },
                 "for" => {
-                    return self.parse_for_loop()
+                    return self.parse_for_loop(start)
+                },
+                "try" => {
+                    return self.parse_try_statement(start)
+                },
+                "throw" => {
+                    let val = self.parse_component(false, 0)?;
+                    return Ok(NodeKind::ThrowStatement(Box::new(val)))
                 }
-                _ => panic!("Unknown keyword \"{}\"", kw)
+                _ => return self.syntax_error(format!("Unexpected keyword \"{}\"", kw), start)
             }
         }
 
@@ -119,77 +137,144 @@ impl Parser {
             }
         }
 
-        print_token(&t);
-        panic!("Unsupported syntax")
+        self.syntax_error(format!("Unsupported syntax, found {}", t.describe()), start)
     }
 
-    fn parse_array_literal (&mut self) -> ASTNode {
-        ASTNode::ArrayLiteral(self.parse_delimited('[', ',', ']', false))
+    fn parse_prefix_operator (&mut self, op: String) -> KaffeeResult<NodeKind> {
+        // Binds tighter than any binary operator, so -a.b * c is (-(a.b)) * c
+        let atom = self.parse_atom(false)?;
+        let operand = Box::new(self.parse_postfix(atom)?);
+
+        if is_update_operator(&op) {
+            self.expect_update_target(&operand)?;
+            return Ok(NodeKind::UpdateNode(UpdateProperties {
+                operator: op,
+                prefix: true,
+                target: operand
+            }))
+        }
+
+        Ok(NodeKind::UnaryNode(UnaryProperties {
+            operator: op,
+            operand
+        }))
     }
 
-    fn parse_for_loop (&mut self) -> ASTNode {
+    fn expect_update_target (&self, target: &ASTNode) -> KaffeeResult<()> {
+        match &target.kind {
+            NodeKind::Identifier(_) | NodeKind::PropertyAccess(_) => Ok(()),
+            _ => self.syntax_error(String::from("++ and -- can only be used on variables and properties"), target.span)
+        }
+    }
+
+    fn parse_array_literal (&mut self) -> KaffeeResult<NodeKind> {
+        Ok(NodeKind::ArrayLiteral(self.parse_delimited('[', ',', ']', false)?))
+    }
+
+    fn parse_for_loop (&mut self, start: Span) -> KaffeeResult<NodeKind> {
         // These are actually transformed into while loops by the parser
         // NOTE: This does lead to quite a few unnecessary scopes
         // TODO: for (let key in object) etc.
         let mut expect_last = false;
         if self.is_next_punctuation('(') {
             // Bracketing for loop conditions is optional
-            self.tokens.read();
+            self.tokens.read()?;
             expect_last = true;
         }
 
-        let decl = self.parse_component(true, 0);
-        let check = self.parse_component(true, 0);
-        let incr = self.parse_component(true, 0);
+        let decl = self.parse_component(true, 0)?;
+        let check = self.parse_component(true, 0)?;
+        let incr = self.parse_component(true, 0)?;
 
-        if expect_last { self.expect_punctuation(')') }
+        if expect_last { self.expect_punctuation(')')? }
 
-        let body = self.parse_component(true, 0);
+        let body = self.parse_component(true, 0)?;
 
         // Performs body of loop, then increments
-        let incr_body = ASTNode::BlockStatement(vec![body, incr]);
+        let body_span = body.span.to(incr.span);
+        let incr_body = ASTNode::new(NodeKind::BlockStatement(vec![body, incr]), body_span);
 
-        let wl = ASTNode::WhileLoop(WhileProperties {
+        let wl = self.node(NodeKind::WhileLoop(WhileProperties {
             check: Box::new(check),
             body: Box::new(incr_body)
-        });
+        }), start);
 
         // Bundles the declaration at the start of the loop
-        ASTNode::BlockStatement(vec![decl, wl])
+        Ok(NodeKind::BlockStatement(vec![decl, wl]))
     }
 
-    fn parse_while_loop (&mut self) -> ASTNode {
-        let check = Box::new(self.parse_component(false, 0));
-        let body = Box::new(self.parse_component(true, 0));
+    fn parse_while_loop (&mut self) -> KaffeeResult<NodeKind> {
+        let check = Box::new(self.parse_component(false, 0)?);
+        let body = Box::new(self.parse_component(true, 0)?);
 
-        ASTNode::WhileLoop(WhileProperties {
+        Ok(NodeKind::WhileLoop(WhileProperties {
             check, body
-        })
+        }))
     }
 
-    fn parse_return_statement (&mut self) -> ASTNode {
-        let val = self.parse_component(false, 0);
-        ASTNode::ReturnStatement(Box::new(val))
+    fn parse_return_statement (&mut self) -> KaffeeResult<NodeKind> {
+        let val = self.parse_component(false, 0)?;
+        Ok(NodeKind::ReturnStatement(Box::new(val)))
     }
 
-    fn parse_if_statement (&mut self) -> ASTNode {
-        let check_exp = Box::new(self.parse_component(false, 0));
-        let body = Box::new(self.parse_component(true, 0));
+    fn parse_if_statement (&mut self) -> KaffeeResult<NodeKind> {
+        let check_exp = Box::new(self.parse_component(false, 0)?);
+        let body = Box::new(self.parse_component(true, 0)?);
 
         let mut else_exp = None;
         if self.is_next_keyword("else") {
-            self.tokens.read();
-            else_exp = Some(Box::new(self.parse_component(true, 0)));
+            self.tokens.read()?;
+            else_exp = Some(Box::new(self.parse_component(true, 0)?));
         }
 
-        ASTNode::IfStatement(IfProperties {
+        Ok(NodeKind::IfStatement(IfProperties {
             check_exp,
             body,
             else_exp
-        })
+        }))
+    }
+
+    fn parse_try_statement (&mut self, start: Span) -> KaffeeResult<NodeKind> {
+        let body = Box::new(self.parse_component(true, 0)?);
+
+        let mut catch_name = None;
+        let mut catch_body = None;
+        if self.is_next_keyword("catch") {
+            self.tokens.read()?;
+
+            // Either catch (e), catch e or just catch
+            if self.is_next_punctuation('(') {
+                self.tokens.read()?;
+                let name_ident = self.parse_atom(false)?;
+                catch_name = Some(self.ident_as_string(&name_ident)?);
+                self.expect_punctuation(')')?;
+            } else if let Token::Identifier(id) = self.tokens.peek().clone() {
+                self.tokens.read()?;
+                catch_name = Some(id);
+            }
+
+            catch_body = Some(Box::new(self.parse_component(true, 0)?));
+        }
+
+        let mut finally_body = None;
+        if self.is_next_keyword("finally") {
+            self.tokens.read()?;
+            finally_body = Some(Box::new(self.parse_component(true, 0)?));
+        }
+
+        if catch_body.is_none() && finally_body.is_none() {
+            return self.syntax_error(String::from("try needs a catch or finally after it"), start)
+        }
+
+        Ok(NodeKind::TryStatement(TryProperties {
+            body,
+            catch_name,
+            catch_body,
+            finally_body
+        }))
     }
 
-    fn parse_function_definition (&mut self) -> ASTNode {
+    fn parse_function_definition (&mut self) -> KaffeeResult<NodeKind> {
         // TODO: Warn when a non-anonymous function is assigned to a variable
         // TODO: Arrow functions
         //       (Maybe if we change the 'function' keyword to 'fn' we wouldn't need this?)
@@ -197,59 +282,64 @@ impl Parser {
 
         // Is this an anonymous function?
         if !self.is_next_punctuation('(') {
-            let name_ident = &self.parse_atom(false);
-            name = self.ident_as_string(name_ident);
+            let name_ident = &self.parse_atom(false)?;
+            name = self.ident_as_string(name_ident)?;
         }
 
-        let args = self.parse_delimited('(', ',', ')', true)
-            .iter().map(|x| self.ident_as_string(x)).collect();
+        let args = self.parse_delimited('(', ',', ')', true)?
+            .iter().map(|x| self.ident_as_string(x)).collect::<KaffeeResult<_>>()?;
 
-        let body_block = self.parse_block_statement(true, true);
+        let body_block = self.parse_block_statement(true, true)?;
 
-        if let ASTNode::BlockStatement(body) = body_block {
-            ASTNode::FunctionDefinition(FunctionDefinitionProperties {
-                name, args, body
-            })
+        if let NodeKind::BlockStatement(body) = body_block {
+            Ok(NodeKind::FunctionDefinition(FunctionDefinitionProperties {
+                name: name.into(),
+                args: Rc::new(args),
+                body: Rc::new(body),
+                slot: None
+            }))
         } else {
             unreachable!()
         }
     }
 
-    fn ident_as_string (&mut self, ident: &ASTNode) -> String {
-        if let ASTNode::Identifier(name) = ident {
-            name.clone()
+    fn ident_as_string (&self, ident: &ASTNode) -> KaffeeResult<String> {
+        if let NodeKind::Identifier(id) = &ident.kind {
+            Ok(id.name.clone())
         } else {
-            panic!("Expected identifier.");
+            self.syntax_error(String::from("Expected an identifier"), ident.span)
         }
     }
 
-    fn parse_object_literal (&mut self) -> ASTNode {
+    fn parse_object_literal (&mut self) -> KaffeeResult<NodeKind> {
         let mut keys = vec![];
         let mut values = vec![];
 
         if !self.is_next_punctuation('}') {
             while !self.tokens.eof {
-                let t = self.tokens.read();
+                let key_span = self.tokens.current_span;
+                let t = self.tokens.read()?;
 
                 if let Token::Identifier(id) = t {
-                    keys.push(id.clone());
+                    keys.push(id.as_str().into());
 
                     if self.is_next_punctuation(',') ||
                        self.is_next_punctuation('}') {
                         // This is an implicit key/value { a, b, c }
-                        values.push(ASTNode::Identifier(id));
+                        values.push(ASTNode::identifier(id, key_span));
                         if self.is_next_punctuation('}') {
-                            self.tokens.read();
+                            self.tokens.read()?;
                             break;
                         }
-                        self.tokens.read();
+                        self.tokens.read()?;
                         continue;
                     } else {
                         // Explicit key/value { a: b }
-                        self.expect_punctuation(':');
-                        values.push(self.parse_component(false, 0));
+                        self.expect_punctuation(':')?;
+                        values.push(self.parse_component(false, 0)?);
 
-                        let nt = self.tokens.read();
+                        let nt_span = self.tokens.current_span;
+                        let nt = self.tokens.read()?;
                         if let Token::Punctuation(pnc) = nt {
                             if pnc == '}' {
                                 break
@@ -258,76 +348,77 @@ impl Parser {
                             }
                         }
 
-                        panic!("Invalid token after value in object literal.")
+                        return self.syntax_error(String::from("Invalid token after value in object literal"), nt_span)
                     }
                 } else {
-                    panic!("Object keys should be identifiers
-(or you left a dangling comma { a, })")
+                    return self.syntax_error(format!("Object keys should be identifiers, found {}
+(or you left a dangling comma {{ a, }})", t.describe()), key_span)
                 }
             }
         } else {
-            self.tokens.read();
+            self.tokens.read()?;
         }
 
-        ASTNode::ObjectLiteral(ObjectLiteralProperties{
+        Ok(NodeKind::ObjectLiteral(ObjectLiteralProperties{
             keys,
             values
-        })
+        }))
     }
 
-    fn parse_variable_declaration (&mut self, constant: bool) -> ASTNode {
-        let nxt = self.parse_component(false, 0);
+    fn parse_variable_declaration (&mut self, constant: bool) -> KaffeeResult<NodeKind> {
+        let nxt = self.parse_component(false, 0)?;
 
-        match nxt {
-            ASTNode::Assignment(assignment) => {
-                return ASTNode::Declaration(DeclarationProperties{
+        match nxt.kind {
+            NodeKind::Assignment(assignment) => {
+                Ok(NodeKind::Declaration(DeclarationProperties{
                     constant,
                     assignment
-                })
+                }))
             },
             _ => {
-                panic!("Declaration (let, const) wasn't followed by an assignment");
+                self.syntax_error(String::from("Declaration (let, const) wasn't followed by an assignment"), nxt.span)
             }
         }
     }
 
-    fn parse_delimited (&mut self, start: char, delim: char, end: char, expect_puncs: bool) -> Vec<ASTNode> {
-        if expect_puncs { self.expect_punctuation(start) }
+    fn parse_delimited (&mut self, start: char, delim: char, end: char, expect_puncs: bool) -> KaffeeResult<Vec<ASTNode>> {
+        if expect_puncs { self.expect_punctuation(start)? }
 
         let mut args = vec![];
         loop {
             // Some delims are empty ()
             if self.is_next_punctuation(end) { break; }
 
-            args.push(self.parse_component(false, 0));
+            args.push(self.parse_component(false, 0)?);
 
             if !self.is_next_punctuation(delim) {
                 break;
             }
 
             // Read the delim char
-            self.tokens.read();
+            self.tokens.read()?;
         }
 
-        if expect_puncs { self.expect_punctuation(end) }
-        return args;
+        if expect_puncs { self.expect_punctuation(end)? }
+        Ok(args)
     }
 
-    fn might_be_assignment (&mut self, me: ASTNode) -> ASTNode {
+    fn might_be_assignment (&mut self, me: ASTNode) -> KaffeeResult<ASTNode> {
         // The clone is to prevent a mutable/immutable borrow
         let t = self.tokens.peek().clone();
 
         if let Token::Operator(op) = t {
             if is_assignment_operator(&op) {
-                self.tokens.read();
-                let right = Box::new(self.parse_component(false, 0));
+                self.tokens.read()?;
+                let right = Box::new(self.parse_component(false, 0)?);
+                let span = me.span.to(right.span);
 
                 if op == "=" {
-                    return ASTNode::Assignment(BinaryProperties {
+                    return Ok(ASTNode::new(NodeKind::Assignment(BinaryProperties {
                         left: Box::new(me),
                         operator: op.clone(),
                         right
-                    })
+                    }), span))
                 }
 
                 // We have an assignment operator
@@ -336,24 +427,24 @@ impl Parser {
                 let bin_op = String::from(&op[0..idx]);
                 // Synthesise a binary node
                 // Transforms a += 1 to a = a + 1
-                let bin = ASTNode::BinaryNode(BinaryProperties {
+                let bin = ASTNode::new(NodeKind::BinaryNode(BinaryProperties {
                     left: Box::new(me.clone()),
                     operator: bin_op,
                     right
-                });
+                }), span);
 
-                return ASTNode::Assignment(BinaryProperties {
+                return Ok(ASTNode::new(NodeKind::Assignment(BinaryProperties {
                     left: Box::new(me),
                     operator: String::from("="),
                     right: Box::new(bin)
-                })
+                }), span))
             }
         }
 
-        me
+        Ok(me)
     }
 
-    fn might_be_binary (&mut self, me: ASTNode, my_precedence: i32) -> ASTNode {
+    fn might_be_binary (&mut self, me: ASTNode, my_precedence: i32) -> KaffeeResult<ASTNode> {
         let t = self.tokens.peek().clone();
 
         if let Token::Operator(op) = t {
@@ -361,78 +452,109 @@ impl Parser {
                 let their_prec = get_operator_precedence(&op);
 
                 if their_prec > my_precedence {
-                    self.tokens.read();
+                    self.tokens.read()?;
 
-                    let them = self.parse_component(false, their_prec);
+                    let them = self.parse_component(false, their_prec)?;
+                    let span = me.span.to(them.span);
 
-                    let node = ASTNode::BinaryNode(BinaryProperties {
+                    let node = ASTNode::new(NodeKind::BinaryNode(BinaryProperties {
                         left: Box::new(me),
                         operator: op,
                         right: Box::new(them)
-                    });
+                    }), span);
 
                     return self.might_be_binary(node, my_precedence)
                 }
             }
         }
 
-        me
+        Ok(me)
     }
 
-    fn might_be_call (&mut self, node: ASTNode) -> (bool, ASTNode) {
+    fn might_be_call (&mut self, node: ASTNode) -> KaffeeResult<(bool, ASTNode)> {
         if self.is_next_punctuation('(') {
-            let args = self.parse_delimited('(', ',', ')', true);
-            return (true, ASTNode::FunctionCall(CallProperties {
+            let args = self.parse_delimited('(', ',', ')', true)?;
+            let start = node.span;
+            return Ok((true, self.node(NodeKind::FunctionCall(CallProperties {
                 callee: Box::new(node),
-                args
-            }))
+                args,
+                tail: false
+            }), start)))
         }
 
-        (false, node)
+        Ok((false, node))
     }
 
-    fn might_be_property_access (&mut self, node: ASTNode) -> (bool, ASTNode) {
+    fn might_be_property_access (&mut self, node: ASTNode) -> KaffeeResult<(bool, ASTNode)> {
         if self.is_next_punctuation('.') {
-            self.tokens.read();
+            self.tokens.read()?;
 
-            let key = self.parse_atom(false);
-            if let ASTNode::Identifier(id) = key {
+            let key = self.parse_atom(false)?;
+            if let NodeKind::Identifier(id) = key.kind {
                 // This transforms obj.key into obj["key"]
-                return (true, ASTNode::PropertyAccess(AccessProperties {
+                let span = node.span.to(key.span);
+                return Ok((true, ASTNode::new(NodeKind::PropertyAccess(AccessProperties {
                     object: Box::new(node),
-                    property: Box::new(ASTNode::String(id))
-                }))
+                    property: Box::new(ASTNode::new(NodeKind::String(id.name.into()), key.span))
+                }), span)))
             } else {
-                panic!("Property access (a.b) key must be an identifier")
+                return self.syntax_error(String::from("Property access (a.b) key must be an identifier"), key.span)
             }
         }
 
-        (false, node)
+        Ok((false, node))
     }
 
-    fn might_be_computed_property_access (&mut self, node: ASTNode) -> (bool, ASTNode) {
+    fn might_be_computed_property_access (&mut self, node: ASTNode) -> KaffeeResult<(bool, ASTNode)> {
         if self.is_next_punctuation('[') {
-            self.tokens.read();
+            self.tokens.read()?;
 
-            let key = self.parse_atom(false);
-            self.expect_punctuation(']');
+            // Any expression can be a key, eg. arr[len(arr) - 1]
+            let key = self.parse_component(false, 0)?;
+            self.expect_punctuation(']')?;
 
-            return (true, ASTNode::PropertyAccess(AccessProperties {
+            let start = node.span;
+            return Ok((true, self.node(NodeKind::PropertyAccess(AccessProperties {
                 object: Box::new(node),
                 property: Box::new(key)
-            }))
+            }), start)))
         }
 
-        (false, node)
+        Ok((false, node))
     }
 
-    fn parse_component (&mut self, accept_statements: bool, prec: i32) -> ASTNode {
-        let mut node = self.parse_atom(accept_statements);
+    fn might_be_postfix_update (&mut self, node: ASTNode) -> KaffeeResult<ASTNode> {
+        if let Token::Operator(op) = self.tokens.peek().clone() {
+            if is_update_operator(&op) {
+                self.expect_update_target(&node)?;
+                self.tokens.read()?;
+
+                let start = node.span;
+                return Ok(self.node(NodeKind::UpdateNode(UpdateProperties {
+                    operator: op,
+                    prefix: false,
+                    target: Box::new(node)
+                }), start))
+            }
+        }
+
+        Ok(node)
+    }
+
+    fn parse_component (&mut self, accept_statements: bool, prec: i32) -> KaffeeResult<ASTNode> {
+        let atom = self.parse_atom(accept_statements)?;
+        let node = self.parse_postfix(atom)?;
+
+        let mba = self.might_be_assignment(node)?;
+        self.might_be_binary(mba, prec)
+    }
 
+    // Property accesses, calls and x++ after an atom
+    fn parse_postfix (&mut self, mut node: ASTNode) -> KaffeeResult<ASTNode> {
         while !self.tokens.eof {
-            let (was_acc, acc_node) = self.might_be_property_access(node.clone());
-            let (was_comp, comp_node) = self.might_be_computed_property_access(acc_node);
-            let (was_call, call_node) = self.might_be_call(comp_node);
+            let (was_acc, acc_node) = self.might_be_property_access(node.clone())?;
+            let (was_comp, comp_node) = self.might_be_computed_property_access(acc_node)?;
+            let (was_call, call_node) = self.might_be_call(comp_node)?;
 
             if !(was_acc || was_call || was_comp) {
                 break;
@@ -441,13 +563,12 @@ impl Parser {
             node = call_node;
         }
 
-        let mba = self.might_be_assignment(node);
-        self.might_be_binary(mba, prec)
+        self.might_be_postfix_update(node)
     }
 
-    fn parse_block_statement (&mut self, expect_first_brace: bool, expect_last_brace: bool) -> ASTNode {
+    fn parse_block_statement (&mut self, expect_first_brace: bool, expect_last_brace: bool) -> KaffeeResult<NodeKind> {
         if expect_first_brace {
-            self.expect_punctuation('{')
+            self.expect_punctuation('{')?
         }
 
         let mut statements = vec![];
@@ -456,30 +577,35 @@ impl Parser {
                 break
             }
 
-            statements.push(self.parse_component(true, 0));
+            statements.push(self.parse_component(true, 0)?);
         }
 
         if expect_last_brace {
-            self.expect_punctuation('}')
+            self.expect_punctuation('}')?
         }
 
-        ASTNode::BlockStatement(statements)
+        Ok(NodeKind::BlockStatement(statements))
     }
 
-    pub fn generate_ast (&mut self) -> Vec<ASTNode> {
-        let bs = self.parse_block_statement(false, false);
+    pub fn generate_ast (&mut self) -> KaffeeResult<Vec<ASTNode>> {
+        let bs = self.parse_block_statement(false, false)?;
 
         match bs {
-            ASTNode::BlockStatement(statements) => statements,
+            NodeKind::BlockStatement(statements) => Ok(statements),
             _ => unreachable!()
         }
     }
 }
 
-pub fn new (code: String) -> Parser {
-    let tk = tokeniser::new(code);
-    let pr = Parser {
+pub fn new (code: String) -> KaffeeResult<Parser> {
+    new_with_source(code, 0)
+}
+
+// The source number ends up in every span, so errors can
+// find the right code when there's more than one piece of it
+pub fn new_with_source (code: String, source: usize) -> KaffeeResult<Parser> {
+    let tk = tokeniser::new(code, source)?;
+    Ok(Parser {
         tokens: tk
-    };
-    pr
+    })
 }
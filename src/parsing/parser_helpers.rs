@@ -1,15 +1,20 @@
 use crate::parsing::parser::Parser;
 use crate::parsing::tokens::*;
+use crate::parsing::ast_utils::*;
+use crate::parsing::span::Span;
+use crate::errors::*;
 
 impl Parser {
-    pub fn expect_punctuation(&mut self, c: char) {
-        let tk = self.tokens.read();
+    pub fn expect_punctuation(&mut self, c: char) -> KaffeeResult<()> {
+        let span = self.tokens.current_span;
+        let tk = self.tokens.read()?;
         if !match tk {
             Token::Punctuation(pnc) => pnc == c,
             _ => false
         } {
-            panic!("Expected punctution '{}'", c)
+            return self.syntax_error(format!("Expected punctuation '{}', found {}", c, tk.describe()), span)
         }
+        Ok(())
     }
 
     pub fn is_next_punctuation(&self, c: char) -> bool {
@@ -25,4 +30,14 @@ impl Parser {
             _ => false
         }
     }
+
+    // Wraps a node kind up with a span from `start` to
+    // the last token that was read
+    pub fn node(&self, kind: NodeKind, start: Span) -> ASTNode {
+        ASTNode::new(kind, start.to(self.tokens.last_span))
+    }
+
+    pub fn syntax_error<T>(&self, message: String, span: Span) -> KaffeeResult<T> {
+        Err(KaffeeError::new(ErrorKind::Syntax, message).with_span(span))
+    }
 }
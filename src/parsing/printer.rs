@@ -28,6 +28,9 @@ pub fn print_token (tk: &Token) {
         },
         Token::Null => {
             println!("Null literal")
+        },
+        Token::Eof => {
+            println!("End of input")
         }
     }
 }
@@ -60,39 +63,57 @@ fn print_object_literal (obj: &ObjectLiteralProperties, depth: i32) {
 }
 
 pub fn print_ast_node (node: &ASTNode, depth: i32) {
-    match node {
-        ASTNode::Declaration(dec) => {
+    match &node.kind {
+        NodeKind::Declaration(dec) => {
             print_at_depth(format!("Declaration, constant: {}", dec.constant), depth);
             print_binary(&dec.assignment, depth + 1);
         },
-        ASTNode::Assignment(asn) => {
+        NodeKind::Assignment(asn) => {
             print_at_depth(String::from("Assignment"), depth);
-            print_binary(&asn, depth + 1);
+            print_binary(asn, depth + 1);
         },
-        ASTNode::String(st) => {
+        NodeKind::String(st) => {
             print_at_depth(format!("String: \"{}\"", st), depth);
         },
-        ASTNode::Number(n) => {
+        NodeKind::Number(n) => {
             print_at_depth(format!("Number: {}", n), depth);
         },
-        ASTNode::Identifier(id) => {
-            print_at_depth(format!("Identifier: \"{}\"", id), depth);
+        NodeKind::Identifier(id) => {
+            let binding = match id.binding {
+                Some(b) => format!(" (depth {}, slot {})", b.depth, b.slot),
+                None => String::new()
+            };
+            print_at_depth(format!("Identifier: \"{}\"{}", id.name, binding), depth);
         },
-        ASTNode::ObjectLiteral(obj) => {
-            print_object_literal(&obj, depth);
+        NodeKind::ObjectLiteral(obj) => {
+            print_object_literal(obj, depth);
         },
-        ASTNode::BlockStatement(bs) => {
+        NodeKind::BlockStatement(bs) => {
             print_at_depth(String::from("Block statement:"), depth);
             for stmt in bs {
                 print_ast_node(stmt, depth + 1);
             }
         },
-        ASTNode::BinaryNode(bn) => {
+        NodeKind::BinaryNode(bn) => {
             print_at_depth(String::from("Binary node:"), depth);
-            print_binary(&bn, depth + 1);
-        },
-        ASTNode::FunctionCall(fnc) => {
-            print_at_depth(String::from("Function call:"), depth);
+            print_binary(bn, depth + 1);
+        },
+        NodeKind::UnaryNode(un) => {
+            print_at_depth(String::from("Unary node:"), depth);
+            print_at_depth(String::from("Operator: ") + &un.operator, depth + 1);
+            print_at_depth(String::from("Operand:"), depth + 1);
+            print_ast_node(un.operand.as_ref(), depth + 2);
+        },
+        NodeKind::UpdateNode(up) => {
+            let fix = if up.prefix { "prefix" } else { "postfix" };
+            print_at_depth(format!("Update node ({}):", fix), depth);
+            print_at_depth(String::from("Operator: ") + &up.operator, depth + 1);
+            print_at_depth(String::from("Target:"), depth + 1);
+            print_ast_node(up.target.as_ref(), depth + 2);
+        },
+        NodeKind::FunctionCall(fnc) => {
+            let kind = if fnc.tail { "Tail call:" } else { "Function call:" };
+            print_at_depth(String::from(kind), depth);
             print_at_depth(String::from("Callee:"), depth + 1);
             print_ast_node(fnc.callee.as_ref(), depth + 2);
             print_at_depth(String::from("Args:"), depth + 1);
@@ -100,28 +121,28 @@ pub fn print_ast_node (node: &ASTNode, depth: i32) {
                 print_ast_node(arg, depth + 2);
             }
         },
-        ASTNode::PropertyAccess(prp) => {
+        NodeKind::PropertyAccess(prp) => {
             print_at_depth(String::from("Property access:"), depth);
             print_at_depth(String::from("Object:"), depth + 1);
             print_ast_node(prp.object.as_ref(), depth + 2);
             print_at_depth(String::from("Property:"), depth + 1);
             print_ast_node(prp.property.as_ref(), depth + 2);
         },
-        ASTNode::FunctionDefinition(fd) => {
+        NodeKind::FunctionDefinition(fd) => {
             print_at_depth(format!("Function definition - \"{}\":", fd.name), depth);
             print_at_depth(String::from("Args:"), depth + 1);
-            for arg in &fd.args {
-                print_at_depth(format!("{}", arg), depth + 2)
+            for arg in fd.args.iter() {
+                print_at_depth(arg.to_string(), depth + 2)
             }
             print_at_depth(String::from("Body:"), depth + 1);
-            for node in &fd.body {
+            for node in fd.body.iter() {
                 print_ast_node(node, depth + 2)
             }
         },
-        ASTNode::Boolean(bl) => {
+        NodeKind::Boolean(bl) => {
             print_at_depth(format!("Boolean - {}", bl), depth)
         },
-        ASTNode::IfStatement(istmt) => {
+        NodeKind::IfStatement(istmt) => {
             print_at_depth(String::from("If Statement:"), depth);
             print_at_depth(String::from("Condition:"), depth + 1);
             print_ast_node(istmt.check_exp.as_ref(), depth + 2);
@@ -134,31 +155,53 @@ pub fn print_ast_node (node: &ASTNode, depth: i32) {
                 print_ast_node(els, depth + 2);
             }
         },
-        ASTNode::Null => {
+        NodeKind::Null => {
             print_at_depth(String::from("Null"), depth);
         },
-        ASTNode::ReturnStatement(rs) => {
+        NodeKind::ReturnStatement(rs) => {
             print_at_depth(String::from("Return statement:"), depth);
             print_ast_node(rs.as_ref(), depth + 1);
         },
-        ASTNode::WhileLoop(wl) => {
+        NodeKind::WhileLoop(wl) => {
             print_at_depth(String::from("While loop:"), depth);
             print_at_depth(String::from("Check:"), depth + 1);
             print_ast_node(wl.check.as_ref(), depth + 2);
             print_at_depth(String::from("Body:"), depth + 1);
             print_ast_node(wl.body.as_ref(), depth + 2);
         },
-        ASTNode::BreakStatement => {
+        NodeKind::BreakStatement => {
             print_at_depth(String::from("Break statement"), depth)
         },
-        ASTNode::ContinueStatement => {
+        NodeKind::ContinueStatement => {
             print_at_depth(String::from("Continue statement"), depth)
         },
-        ASTNode::ArrayLiteral(items) => {
+        NodeKind::ArrayLiteral(items) => {
             print_at_depth(format!("Array (len {}):", items.len()), depth);
             for i in items {
                 print_ast_node(i, depth + 1)
             }
+        },
+        NodeKind::TryStatement(ts) => {
+            print_at_depth(String::from("Try statement:"), depth);
+            print_at_depth(String::from("Body:"), depth + 1);
+            print_ast_node(ts.body.as_ref(), depth + 2);
+
+            if let Some(cb) = &ts.catch_body {
+                match &ts.catch_name {
+                    Some(name) => print_at_depth(format!("Catch \"{}\":", name), depth + 1),
+                    None => print_at_depth(String::from("Catch:"), depth + 1)
+                }
+                print_ast_node(cb, depth + 2);
+            }
+
+            if let Some(fb) = &ts.finally_body {
+                print_at_depth(String::from("Finally:"), depth + 1);
+                print_ast_node(fb, depth + 2);
+            }
+        },
+        NodeKind::ThrowStatement(ts) => {
+            print_at_depth(String::from("Throw statement:"), depth);
+            print_ast_node(ts.as_ref(), depth + 1);
         }
     }
 }
@@ -0,0 +1,31 @@
+// Lines and columns both start at 1, and columns count chars
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Position {
+    pub line: usize,
+    pub col: usize
+}
+
+// A range of source code. The end is exclusive.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Span {
+    // Which piece of code this came from - the REPL
+    // parses each entry separately
+    pub source: usize,
+    pub start: Position,
+    pub end: Position
+}
+
+impl Span {
+    // The span covering both this one and a later one
+    pub fn to (&self, other: Span) -> Span {
+        Span {
+            source: self.source,
+            start: self.start,
+            end: other.end
+        }
+    }
+}
+
+pub fn start_position () -> Position {
+    Position { line: 1, col: 1 }
+}
@@ -1,13 +1,21 @@
 use crate::parsing::char_stream;
 use crate::parsing::char_stream::CharStream;
 use crate::parsing::tokens::*;
+use crate::parsing::span::*;
+use crate::errors::*;
 use std::iter::FromIterator;
 
 // Tokeniser acts like a stream
 pub struct Tokeniser {
     pub code: CharStream,
     pub current: Token,
-    pub eof: bool
+    pub eof: bool,
+    // Passed on to spans so errors know which code they're in
+    pub source: usize,
+    // Where the peeked token is
+    pub current_span: Span,
+    // Where the token that was last read() is
+    pub last_span: Span
 }
 
 impl Tokeniser {
@@ -17,38 +25,61 @@ impl Tokeniser {
         }
     }
 
-    fn read_next (&mut self) {
+    fn span_from (&self, start: Position) -> Span {
+        Span {
+            source: self.source,
+            start,
+            end: self.code.position
+        }
+    }
+
+    fn read_next (&mut self) -> KaffeeResult<()> {
         self.eat_whitespace();
+        let start = self.code.position;
 
         if self.code.eof {
             self.eof = true;
-            return;
+            self.current = Token::Eof;
+            self.current_span = self.span_from(start);
+            return Ok(());
         }
 
         let c = self.code.read();
 
-        // TODO: Fix reading of numbers like .5 as properties
-        //       See https://github.com/adamsoutar/ajs/blob/d392fcd388a5cb3e044a7fcd32534d7b816520a7/parser/tokeniser.go#L108
-        if c == '"' {
-            self.current = self.read_string();
-        } else if c == '/' && self.code.peek() == '/' {
+        if c == '/' && self.code.peek() == '/' {
             self.read_single_line_comment();
-            self.read_next();
+            return self.read_next();
         } else if c == '/' && self.code.peek() == '*' {
             self.read_multi_line_comment();
-            self.read_next();
+            return self.read_next();
+        }
+
+        let token = self.read_token(c);
+        let span = self.span_from(start);
+        self.current = token.map_err(|e| e.with_span(span))?;
+        self.current_span = span;
+
+        Ok(())
+    }
+
+    fn read_token (&mut self, c: char) -> KaffeeResult<Token> {
+        // TODO: Fix reading of numbers like .5 as properties
+        //       See https://github.com/adamsoutar/ajs/blob/d392fcd388a5cb3e044a7fcd32534d7b816520a7/parser/tokeniser.go#L108
+        if c == '"' {
+            self.read_string()
         } else if is_identifier_start(&c) {
-            self.current = self.read_identifier(c);
+            Ok(self.read_identifier(c))
         } else if is_operator_char(&c) {
-            self.current = self.read_operator(c);
+            self.read_operator(c)
         } else if is_punctuation(&c) {
             // Punctuation is just one char, doesn't need a
             // read method
-            self.current = Token::Punctuation(c);
+            Ok(Token::Punctuation(c))
         } else if is_number(&c) {
-            self.current = self.read_number(c);
+            self.read_number(c)
         } else {
-            panic!("Invalid syntax - unexpected character {} in code", c);
+            Err(KaffeeError::new(ErrorKind::Syntax,
+                format!("Unexpected character '{}' in code", c)))
         }
     }
 
@@ -69,18 +100,23 @@ impl Tokeniser {
         self.code.read();
     }
 
-    fn read_operator (&mut self, first: char) -> Token {
-        let mut op = vec![first];
+    fn read_operator (&mut self, first: char) -> KaffeeResult<Token> {
+        // Reads the longest operator it can, so that operators
+        // next to each other (x=-1, !!done) are split up
+        let mut st = first.to_string();
         while !self.code.eof && is_operator_char(&self.code.peek()) {
-            op.push(self.code.read());
+            let longer = format!("{}{}", st, self.code.peek());
+            if !is_operator_prefix(&longer) { break }
+            st = longer;
+            self.code.read();
         }
-        let st = String::from_iter(op);
 
         if !is_operator(&st) {
-            panic!("\"{}\" is not a valid operator", st)
+            return Err(KaffeeError::new(ErrorKind::Syntax,
+                format!("\"{}\" is not a valid operator", st)))
         }
 
-        Token::Operator(st)
+        Ok(Token::Operator(st))
     }
 
     fn read_identifier (&mut self, first: char) -> Token {
@@ -104,43 +140,65 @@ impl Tokeniser {
         }
     }
 
-    fn read_string (&mut self) -> Token {
+    fn read_string (&mut self) -> KaffeeResult<Token> {
         let mut chars = vec![];
         // TODO: Escape sequences
         while !self.code.eof && self.code.peek() != '"' {
             chars.push(self.code.read());
         }
+        if self.code.eof {
+            return Err(KaffeeError::new(ErrorKind::Syntax,
+                String::from("Unterminated string literal")))
+        }
         self.code.read();
-        Token::String(chars.iter().collect())
+        Ok(Token::String(chars.iter().collect()))
     }
 
-    fn read_number (&mut self, first: char) -> Token {
+    fn read_number (&mut self, first: char) -> KaffeeResult<Token> {
         let mut vc = vec![first];
         while !self.code.eof && is_number(&self.code.peek()) {
             vc.push(self.code.read())
         }
         let st = String::from_iter(vc);
-        Token::Number(st.parse().unwrap())
+        match st.parse() {
+            Ok(n) => Ok(Token::Number(n)),
+            Err(_) => Err(KaffeeError::new(ErrorKind::Syntax,
+                format!("\"{}\" is not a valid number", st)))
+        }
     }
 
     pub fn peek (&self) -> &Token {
         &self.current
     }
 
-    pub fn read (&mut self) -> Token {
+    pub fn read (&mut self) -> KaffeeResult<Token> {
+        if self.eof {
+            return Err(KaffeeError::new(ErrorKind::Syntax,
+                String::from("Unexpected end of input")).with_span(self.current_span))
+        }
+
         let tk = self.current.clone();
-        self.read_next();
-        tk
+        self.last_span = self.current_span;
+        self.read_next()?;
+        Ok(tk)
     }
 }
 
-pub fn new (code: String) -> Tokeniser {
+pub fn new (code: String, source: usize) -> KaffeeResult<Tokeniser> {
     let cs = char_stream::new(code);
+    let span = Span {
+        source,
+        start: start_position(),
+        end: start_position()
+    };
     let mut tk = Tokeniser {
         code: cs,
         current: Token::Number(0.),
-        eof: false
+        eof: false,
+        source,
+        current_span: span,
+        last_span: span
     };
-    tk.read_next();
-    tk
+    tk.read_next()?;
+    Ok(tk)
 }
@@ -1,4 +1,4 @@
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum Token {
     String(String),
     Identifier(String),
@@ -7,7 +7,26 @@ pub enum Token {
     Boolean(bool),
     Null,
     Operator(String),
-    Punctuation(char)
+    Punctuation(char),
+    // Returned by peek() once all the code has been read
+    Eof
+}
+
+impl Token {
+    // How the token is referred to in syntax errors
+    pub fn describe (&self) -> String {
+        match self {
+            Token::String(st) => format!("string \"{}\"", st),
+            Token::Identifier(id) => format!("identifier \"{}\"", id),
+            Token::Keyword(kw) => format!("keyword \"{}\"", kw),
+            Token::Number(n) => format!("number {}", n),
+            Token::Boolean(bl) => format!("boolean {}", bl),
+            Token::Null => String::from("null"),
+            Token::Operator(op) => format!("operator \"{}\"", op),
+            Token::Punctuation(pnc) => format!("'{}'", pnc),
+            Token::Eof => String::from("end of input")
+        }
+    }
 }
 
 // TODO: Don't redefine the vectors in each
@@ -24,7 +43,7 @@ fn in_char_string (c: &char, s: &str) -> bool {
 }
 
 pub fn is_whitespace (c: &char) -> bool {
-    let whitespace = vec![' ', '\t', '\n', ';'];
+    let whitespace = [' ', '\t', '\n', ';'];
     whitespace.contains(c)
 }
 
@@ -36,17 +55,28 @@ pub fn is_number(c: &char) -> bool {
 pub fn is_keyword(s: &String) -> bool {
     in_string_vector(s, vec![
         "let", "const", "fn", "if", "else",
-        "return", "while", "break", "continue", "for"
+        "return", "while", "break", "continue", "for",
+        "try", "catch", "finally", "throw"
     ])
 }
 
-pub fn is_operator(s: &String) -> bool {
-    in_string_vector(s, vec![
+fn operators () -> Vec<&'static str> {
+    vec![
         "=", "==", "+", "-", "*", "/",
         "!=", "**", "%", "&&", "||",
         ">", "<", ">=", "<=", "+=", "*=",
-        "-=", "/=", "%=", "**="
-    ])
+        "-=", "/=", "%=", "**=", "++", "--",
+        "!"
+    ]
+}
+
+pub fn is_operator(s: &String) -> bool {
+    in_string_vector(s, operators())
+}
+// Whether some operator starts with s, so the tokeniser knows
+// whether to keep reading (eg. x=-1 is "=" then "-")
+pub fn is_operator_prefix(s: &str) -> bool {
+    operators().iter().any(|op| op.starts_with(s))
 }
 pub fn is_assignment_operator (s: &String) -> bool {
     // NOTE: When these are AST Transformed (*= etc),
@@ -57,12 +87,20 @@ pub fn is_assignment_operator (s: &String) -> bool {
         "**="
     ])
 }
+// ++ and --, prefix or postfix
+pub fn is_update_operator (s: &String) -> bool {
+    in_string_vector(s, vec!["++", "--"])
+}
+// Operators that can go before an expression, like -x or !done
+pub fn is_unary_operator (s: &String) -> bool {
+    in_string_vector(s, vec!["!", "-", "+"])
+}
 pub fn is_binary_operator (s: &String) -> bool {
-    is_operator(s) && !is_assignment_operator(s)
+    is_operator(s) && !is_assignment_operator(s) &&
+        !is_update_operator(s) && s != "!"
 }
-pub fn get_operator_precedence (s: &String) -> i32 {
-    let sstr = &s[..];
-    match sstr {
+pub fn get_operator_precedence (s: &str) -> i32 {
+    match s {
         "||" => 5,
         "&&" => 6,
         "==" => 11,
@@ -0,0 +1,144 @@
+use kaffee::interpretting::interpreter;
+use kaffee::interpretting::interpreter_utils::KaffeeValue;
+use kaffee::parsing::ast_utils::NodeKind;
+use kaffee::parsing::printer::print_ast;
+use kaffee::std_lib::functions::stringify_member;
+use kaffee::{Interpreter, KaffeeResult, Backend};
+use std::io::{self, BufRead, Write};
+
+// Counts how many brackets are left open, ignoring any inside
+// strings and comments. Unterminated strings and comments count
+// as open too, so the REPL knows to keep reading.
+fn unclosed_brackets (code: &str) -> i32 {
+    let chars: Vec<char> = code.chars().collect();
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        if c == '"' {
+            i += 1;
+            while i < chars.len() && chars[i] != '"' { i += 1 }
+            if i >= chars.len() { return depth + 1 }
+        } else if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' { i += 1 }
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i + 1 < chars.len() && !(chars[i] == '*' && chars[i + 1] == '/') { i += 1 }
+            if i + 1 >= chars.len() { return depth + 1 }
+            i += 1;
+        } else if "{([".contains(c) {
+            depth += 1;
+        } else if "})]".contains(c) {
+            depth -= 1;
+        }
+
+        i += 1;
+    }
+
+    depth
+}
+
+// Reads one complete entry, which may span several lines.
+// Returns None once stdin is closed.
+fn read_entry (stdin: &io::Stdin) -> Option<String> {
+    let mut entry = String::new();
+    let mut prompt = "> ";
+
+    loop {
+        print!("{}", prompt);
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => {
+                // Ctrl-D halfway through an entry just drops it
+                println!();
+                return None
+            },
+            Ok(_) => {}
+        }
+
+        entry += &line;
+        if unclosed_brackets(&entry) <= 0 {
+            return Some(entry)
+        }
+        prompt = "... ";
+    }
+}
+
+fn eval_entry (interp: &mut Interpreter, entry: String, print_ast_nodes: bool) -> KaffeeResult<()> {
+    let ast = interp.parse(entry)?;
+    if print_ast_nodes { print_ast(&ast) }
+
+    for node in &ast {
+        let val = interp.eval_statement(node)?;
+
+        // Calls like println() don't need to echo their null
+        if let NodeKind::FunctionCall(_) = node.kind {
+            if val == Some(KaffeeValue::Null) { continue }
+        }
+        if let Some(v) = val {
+            println!("{}", stringify_member(&v, &mut interp.vars));
+        }
+    }
+    Ok(())
+}
+
+pub fn start (print_ast_nodes: bool, backend: Backend, optimize: bool) {
+    println!("Kaffee REPL - press Ctrl-D to exit");
+
+    let mut interp = interpreter::new(String::new())
+        .expect("An empty program always parses");
+    interp.backend = backend;
+    interp.optimize = optimize;
+    interp.limits.max_call_depth = Some(crate::MAX_CALL_DEPTH);
+
+    let stdin = io::stdin();
+    while let Some(entry) = read_entry(&stdin) {
+        if entry.trim().is_empty() { continue }
+
+        if let Err(e) = eval_entry(&mut interp, entry, print_ast_nodes) {
+            println!("{}", interp.render_error(&e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unclosed_brackets;
+
+    #[test]
+    fn counts_open_brackets () {
+        assert_eq!(unclosed_brackets("let x = 1"), 0);
+        assert_eq!(unclosed_brackets("fn f (a) {"), 1);
+        assert_eq!(unclosed_brackets("let o = { list: [f(1,"), 3);
+        assert_eq!(unclosed_brackets("let o = { list: [f(1)] }"), 0);
+    }
+
+    #[test]
+    fn ignores_brackets_in_strings_and_comments () {
+        assert_eq!(unclosed_brackets("println(\"{[(\")"), 0);
+        assert_eq!(unclosed_brackets("let s = \"}\" {"), 1);
+        assert_eq!(unclosed_brackets("{ // }"), 1);
+        assert_eq!(unclosed_brackets("{ /* } */ }"), 0);
+    }
+
+    #[test]
+    fn unterminated_strings_and_comments_stay_open () {
+        assert_eq!(unclosed_brackets("let s = \"abc"), 1);
+        assert_eq!(unclosed_brackets("{ let s = \"}"), 2);
+        assert_eq!(unclosed_brackets("/* {"), 1);
+    }
+
+    // Extra closers leave the count below zero, so the entry is
+    // handed over and the parser reports them
+    #[test]
+    fn unbalanced_closers_go_negative () {
+        assert_eq!(unclosed_brackets("}"), -1);
+        assert_eq!(unclosed_brackets("f(1))"), -1);
+        assert_eq!(unclosed_brackets("] {"), 0);
+    }
+}
@@ -1,59 +1,270 @@
 use std::io;
+use std::collections::{HashMap, HashSet};
 use crate::interpretting::interpreter_utils::*;
+use crate::interpretting::variables;
 use crate::interpretting::variables::Variables;
+use crate::errors::*;
 
-// TODO: To be able to stringify objects, we need access to the alloc array
-fn rust_stringify (value: &KaffeeValue, _: &mut Variables) -> String {
-    match value {
-        KaffeeValue::Number(n) => format!("{}", n),
-        KaffeeValue::String(st) => st.clone(),
-        KaffeeValue::Boolean(bl) => format!("{}", bl),
-        // TODO: Exception for this
-        _ => panic!("Unstringifyable value")
+pub fn rust_stringify (value: &KaffeeValue, vars: &mut Variables) -> String {
+    stringify_parts(Piece::Value(value.clone(), false), vars)
+}
+
+// Strings inside arrays and objects are quoted so that
+// [1, "1"] doesn't look like [1, 1]
+pub fn stringify_member (value: &KaffeeValue, vars: &mut Variables) -> String {
+    stringify_parts(Piece::Value(value.clone(), true), vars)
+}
+
+// What's left to write, last first
+enum Piece {
+    // A value, and whether it's quoted if it's a string
+    Value(KaffeeValue, bool),
+    Text(&'static str),
+    // The end of an array or object, which can be shown in full again
+    Leave(KaffeeValue)
+}
+
+// Arrays and objects can hold themselves, so the ones we're already
+// inside are shown as [...] or {...} rather than going round forever.
+// They're written a piece at a time rather than recursively, so deep
+// nesting doesn't run out of stack.
+fn stringify_parts (first: Piece, vars: &mut Variables) -> String {
+    let mut out = String::new();
+    let mut pieces = vec![first];
+    let mut arrays = HashSet::new();
+    let mut objects = HashSet::new();
+
+    while let Some(piece) = pieces.pop() {
+        let (value, quoted) = match piece {
+            Piece::Value(value, quoted) => (value, quoted),
+            Piece::Text(text) => { out.push_str(text); continue },
+            Piece::Leave(KaffeeValue::Array(arr)) => { arrays.remove(&arr); continue },
+            Piece::Leave(KaffeeValue::Object(obj)) => { objects.remove(&obj); continue },
+            Piece::Leave(_) => unreachable!()
+        };
+
+        match &value {
+            KaffeeValue::Number(n) => out.push_str(&n.to_string()),
+            KaffeeValue::String(st) if quoted => out.push_str(&format!("\"{}\"", st)),
+            KaffeeValue::String(st) => out.push_str(st),
+            KaffeeValue::Boolean(bl) => out.push_str(&bl.to_string()),
+            KaffeeValue::Null => out.push_str("null"),
+            KaffeeValue::NativeFunction(nm) => out.push_str(&format!("[NativeFunction {}]", nm.name)),
+            KaffeeValue::Function(_) => out.push_str("[Function]"),
+            KaffeeValue::Array(arr) => {
+                if !arrays.insert(*arr) {
+                    out.push_str("[...]");
+                    continue
+                }
+                out.push('[');
+                pieces.push(Piece::Leave(value.clone()));
+                pieces.push(Piece::Text("]"));
+                for (i, item) in vars.array_items(*arr).into_iter().enumerate().rev() {
+                    pieces.push(Piece::Value(item, true));
+                    if i > 0 { pieces.push(Piece::Text(", ")) }
+                }
+            },
+            KaffeeValue::Object(obj) => {
+                if objects.contains(obj) {
+                    out.push_str("{...}");
+                    continue
+                }
+                let entries = vars.object_entries(*obj);
+                if entries.is_empty() {
+                    out.push_str("{}");
+                    continue
+                }
+                objects.insert(*obj);
+                out.push_str("{ ");
+                pieces.push(Piece::Leave(value.clone()));
+                pieces.push(Piece::Text(" }"));
+                for (i, (key, val)) in entries.into_iter().enumerate().rev() {
+                    pieces.push(Piece::Value(val, true));
+                    pieces.push(Piece::Text(": "));
+                    pieces.push(Piece::Value(key.to_value(), false));
+                    if i > 0 { pieces.push(Piece::Text(", ")) }
+                }
+            }
+        }
     }
+    out
 }
 
-fn native_println (args: Vec<KaffeeValue>, vars: &mut Variables) -> KaffeeValue {
+fn native_println (args: Vec<KaffeeValue>, vars: &mut Variables) -> KaffeeResult<KaffeeValue> {
     println!("{}", rust_stringify(&args[0], vars));
-    KaffeeValue::Null
+    Ok(KaffeeValue::Null)
 }
 
-fn native_stringify(args: Vec<KaffeeValue>, vars: &mut Variables) -> KaffeeValue {
-    KaffeeValue::String(rust_stringify(&args[0], vars))
+fn native_stringify(args: Vec<KaffeeValue>, vars: &mut Variables) -> KaffeeResult<KaffeeValue> {
+    Ok(KaffeeValue::String(rust_stringify(&args[0], vars).into()))
 }
 
-fn native_len(args: Vec<KaffeeValue>, _: &mut Variables) -> KaffeeValue {
-    KaffeeValue::Number(match &args[0] {
+fn native_len(args: Vec<KaffeeValue>, vars: &mut Variables) -> KaffeeResult<KaffeeValue> {
+    Ok(KaffeeValue::Number(match &args[0] {
         KaffeeValue::String(st) => st.len(),
-        KaffeeValue::Array(ar) => ar.len(),
-        _ => panic!("Value has no discernable length")
-    } as f64)
+        KaffeeValue::Array(arr) => vars.arrays[*arr].len(),
+        _ => return Err(KaffeeError::new(ErrorKind::Type,
+            String::from("Value has no discernable length")))
+    } as f64))
+}
+
+fn expect_array (value: &KaffeeValue, func: &str) -> KaffeeResult<ArrayHandle> {
+    match value {
+        KaffeeValue::Array(arr) => Ok(*arr),
+        _ => Err(KaffeeError::new(ErrorKind::Type,
+            format!("{}'s first arg should be an array", func)))
+    }
+}
+
+// An index into arr, which can be one past the end if allow_end is set
+fn expect_index (value: &KaffeeValue, arr: ArrayHandle, allow_end: bool, vars: &Variables) -> KaffeeResult<usize> {
+    let len = vars.arrays[arr].len();
+    match variables::array_index(value) {
+        Some(i) if i < len || (allow_end && i == len) => Ok(i),
+        Some(i) => Err(KaffeeError::new(ErrorKind::Key,
+            format!("Index {} is out of range for an array of length {}", i, len))),
+        None => Err(KaffeeError::new(ErrorKind::Key,
+            String::from("Arrays can only be indexed with whole numbers")))
+    }
+}
+
+// Adds to the array in place, and gives it back
+// so `list = append(list, x)` still works
+fn native_append(args: Vec<KaffeeValue>, vars: &mut Variables) -> KaffeeResult<KaffeeValue> {
+    let arr = expect_array(&args[0], "append")?;
+    vars.array_push(arr, args[1].clone());
+    Ok(KaffeeValue::Array(arr))
+}
+
+// Returns the new length
+fn native_push(args: Vec<KaffeeValue>, vars: &mut Variables) -> KaffeeResult<KaffeeValue> {
+    let arr = expect_array(&args[0], "push")?;
+    vars.array_push(arr, args[1].clone());
+    Ok(KaffeeValue::Number(vars.arrays[arr].len() as f64))
+}
+
+fn native_pop(args: Vec<KaffeeValue>, vars: &mut Variables) -> KaffeeResult<KaffeeValue> {
+    let arr = expect_array(&args[0], "pop")?;
+    match vars.arrays[arr].len() {
+        0 => Err(KaffeeError::new(ErrorKind::Key,
+            String::from("Can't pop from an empty array"))),
+        len => Ok(vars.array_remove(arr, len - 1))
+    }
+}
+
+fn native_insert(args: Vec<KaffeeValue>, vars: &mut Variables) -> KaffeeResult<KaffeeValue> {
+    let arr = expect_array(&args[0], "insert")?;
+    let index = expect_index(&args[1], arr, true, vars)?;
+    vars.array_insert(arr, index, args[2].clone());
+    Ok(KaffeeValue::Null)
+}
+
+// Returns the removed item
+fn native_remove(args: Vec<KaffeeValue>, vars: &mut Variables) -> KaffeeResult<KaffeeValue> {
+    let arr = expect_array(&args[0], "remove")?;
+    let index = expect_index(&args[1], arr, false, vars)?;
+    Ok(vars.array_remove(arr, index))
 }
 
-fn native_append(args: Vec<KaffeeValue>, vars: &mut Variables) -> KaffeeValue {
-    let mut arr = match &args[0] {
-        KaffeeValue::Array(itms) => itms.clone(),
-        _ => panic!("Append's first arg should be an array")
+// A new array or object holding the same values.
+// Anything they point to is shared with the original.
+fn native_clone(args: Vec<KaffeeValue>, vars: &mut Variables) -> KaffeeResult<KaffeeValue> {
+    Ok(match &args[0] {
+        KaffeeValue::Array(arr) => {
+            let items = vars.array_items(*arr);
+            vars.new_array(items)
+        },
+        KaffeeValue::Object(obj) => {
+            let copy = vars.alloc_object(new_object_value());
+            for (key, val) in vars.object_entries(*obj) {
+                vars.insert_into_object(key, val, copy);
+            }
+            KaffeeValue::Object(copy)
+        },
+        // Everything else is immutable anyway
+        other => other.clone()
+    })
+}
+
+// Copies arrays and objects all the way down. Anything that's
+// reachable more than once (including through a cycle) is copied
+// once, so the copy has the same shape as the original.
+fn native_deep_clone(args: Vec<KaffeeValue>, vars: &mut Variables) -> KaffeeResult<KaffeeValue> {
+    let mut copies = Copies {
+        arrays: HashMap::new(),
+        objects: HashMap::new(),
+        unfilled: vec![]
     };
+    let copy = copies.copy_of(&args[0], vars);
+
+    // Filled in one at a time rather than recursively,
+    // so deep nesting doesn't run out of stack
+    while let Some(next) = copies.unfilled.pop() {
+        match next {
+            (KaffeeValue::Array(arr), KaffeeValue::Array(copy)) => {
+                for item in vars.array_items(arr) {
+                    let item = copies.copy_of(&item, vars);
+                    vars.array_push(copy, item);
+                }
+            },
+            (KaffeeValue::Object(obj), KaffeeValue::Object(copy)) => {
+                // Keys stay the same, even if they're arrays or objects
+                for (key, val) in vars.object_entries(obj) {
+                    let val = copies.copy_of(&val, vars);
+                    vars.insert_into_object(key, val, copy);
+                }
+            },
+            _ => unreachable!()
+        }
+    }
+    Ok(copy)
+}
 
-    let idx = vars.alloc_value(args[1].clone(), false);
-    arr.push(idx);
+// What's been copied so far, original to copy
+struct Copies {
+    arrays: HashMap<ArrayHandle, ArrayHandle>,
+    objects: HashMap<ObjectHandle, ObjectHandle>,
+    // Copies that are still empty, with what to fill them from
+    unfilled: Vec<(KaffeeValue, KaffeeValue)>
+}
 
-    KaffeeValue::Array(arr)
+impl Copies {
+    // The copy of a value, made empty if it hasn't been copied yet.
+    // Making it first means anything that points back at it finds it.
+    fn copy_of (&mut self, value: &KaffeeValue, vars: &mut Variables) -> KaffeeValue {
+        let copy = match value {
+            KaffeeValue::Array(arr) => {
+                if let Some(copy) = self.arrays.get(arr) {
+                    return KaffeeValue::Array(*copy)
+                }
+                let copy = vars.alloc_array(vec![]);
+                self.arrays.insert(*arr, copy);
+                KaffeeValue::Array(copy)
+            },
+            KaffeeValue::Object(obj) => {
+                if let Some(copy) = self.objects.get(obj) {
+                    return KaffeeValue::Object(*copy)
+                }
+                let copy = vars.alloc_object(new_object_value());
+                self.objects.insert(*obj, copy);
+                KaffeeValue::Object(copy)
+            },
+            // Functions keep sharing the scopes they captured
+            other => return other.clone()
+        };
+        self.unfilled.push((value.clone(), copy.clone()));
+        copy
+    }
 }
 
-fn native_input(_: Vec<KaffeeValue>, _: &mut Variables) -> KaffeeValue {
+fn native_input(_: Vec<KaffeeValue>, _: &mut Variables) -> KaffeeResult<KaffeeValue> {
     let mut input = String::new();
     match io::stdin().read_line(&mut input) {
         Ok(_) => {
             // Chop the \n off the end.
-            KaffeeValue::String(
-                input
-                    .chars()
-                    .take(input.len() - 1)
-                    .collect())
+            Ok(KaffeeValue::String(input.trim_end_matches(&['\n', '\r'][..]).into()))
         },
-        Err(error) => panic!(error)
+        Err(error) => Err(KaffeeError::new(ErrorKind::Io, error.to_string()))
     }
 }
 
@@ -79,6 +290,36 @@ pub fn get_std_lib_mappings () -> Vec<NativeMapping> {
             arg_count: 2,
             func: native_append
         },
+        NativeMapping {
+            name: "push".to_string(),
+            arg_count: 2,
+            func: native_push
+        },
+        NativeMapping {
+            name: "pop".to_string(),
+            arg_count: 1,
+            func: native_pop
+        },
+        NativeMapping {
+            name: "insert".to_string(),
+            arg_count: 3,
+            func: native_insert
+        },
+        NativeMapping {
+            name: "remove".to_string(),
+            arg_count: 2,
+            func: native_remove
+        },
+        NativeMapping {
+            name: "clone".to_string(),
+            arg_count: 1,
+            func: native_clone
+        },
+        NativeMapping {
+            name: "deep_clone".to_string(),
+            arg_count: 1,
+            func: native_deep_clone
+        },
         NativeMapping {
             name: "input".to_string(),
             arg_count: 0,
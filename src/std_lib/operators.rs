@@ -1,65 +1,136 @@
 use crate::interpretting::interpreter_utils::*;
+use crate::errors::*;
 
-pub fn operator_handler (left: KaffeeValue, op: &String, right: KaffeeValue) -> KaffeeValue {
-    // TODO: This isn't very clean
-    let generic_ops = vec!["==", "!="];
-    if generic_ops.contains(&&op[..]) {
-        return generic(left, op, right)
+fn invalid_operator<T> (op: &str, types: &str) -> KaffeeResult<T> {
+    Err(KaffeeError::new(ErrorKind::Type,
+        format!("Invalid operator for two {} types \"{}\"", types, op)))
+}
+
+pub fn operator_handler (left: KaffeeValue, op: &String, right: KaffeeValue) -> KaffeeResult<KaffeeValue> {
+    match BinaryOp::from_operator(op) {
+        Some(bop) => binary(left, bop, right),
+        None => Err(KaffeeError::new(ErrorKind::Syntax,
+            format!("Unknown binary operator \"{}\"", op)))
+    }
+}
+
+// Every binary operator apart from && and ||, which
+// don't always look at their right side
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BinaryOp {
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+    GreaterEqual,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Power
+}
+
+impl BinaryOp {
+    pub fn from_operator (op: &str) -> Option<BinaryOp> {
+        Some(match op {
+            "==" => BinaryOp::Equal,
+            "!=" => BinaryOp::NotEqual,
+            ">" => BinaryOp::Greater,
+            "<" => BinaryOp::Less,
+            ">=" => BinaryOp::GreaterEqual,
+            "<=" => BinaryOp::LessEqual,
+            "+" => BinaryOp::Add,
+            "-" => BinaryOp::Subtract,
+            "*" => BinaryOp::Multiply,
+            "/" => BinaryOp::Divide,
+            "%" => BinaryOp::Remainder,
+            "**" => BinaryOp::Power,
+            _ => return None
+        })
+    }
+
+    pub fn as_str (&self) -> &'static str {
+        match self {
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Greater => ">",
+            BinaryOp::Less => "<",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Remainder => "%",
+            BinaryOp::Power => "**"
+        }
+    }
+}
+
+pub fn binary (left: KaffeeValue, op: BinaryOp, right: KaffeeValue) -> KaffeeResult<KaffeeValue> {
+    // Some operators work on all types
+    match op {
+        BinaryOp::Equal => return Ok(KaffeeValue::Boolean(left == right)),
+        BinaryOp::NotEqual => return Ok(KaffeeValue::Boolean(left != right)),
+        _ => {}
     }
 
     match (left, right) {
         (KaffeeValue::Number(n1), KaffeeValue::Number(n2)) => maths(n1, op, n2),
-        (KaffeeValue::Boolean(b1), KaffeeValue::Boolean(b2)) => bools(b1, op, b2),
-        (KaffeeValue::String(s1), KaffeeValue::String(s2)) => strings(s1, op, s2),
-        _ => panic!("Invalid binary operation type signature.")
+        (KaffeeValue::String(s1), KaffeeValue::String(s2)) => strings(&s1, op, &s2),
+        _ => Err(KaffeeError::new(ErrorKind::Type,
+            format!("Invalid binary operation type signature for \"{}\"", op.as_str())))
     }
 }
 
-// Some operators work on all types
-fn generic (l: KaffeeValue, op: &String, r: KaffeeValue) -> KaffeeValue {
-    KaffeeValue::Boolean(match &op[..] {
-        "==" => l == r,
-        "!=" => l != r,
-        _ => unreachable!()
-    })
+// null and false are the only falsy values
+pub fn is_truthy (value: &KaffeeValue) -> bool {
+    match value {
+        KaffeeValue::Boolean(bl) => *bl,
+        KaffeeValue::Null => false,
+        _ => true
+    }
 }
 
-// Ops with two numbers
-fn maths (l: f64, op: &String, r: f64) -> KaffeeValue {
-    let bin_results = vec![">", "<", ">=", "<="];
-    if bin_results.contains(&&op[..]) {
-        return KaffeeValue::Boolean(match &op[..] {
-            ">" => l > r,
-            "<" => l < r,
-            ">=" => l >= r,
-            "<=" => l <= r,
-            _ => unreachable!()
-        })
+// -x, +x and !x
+pub fn unary_handler (op: &str, value: KaffeeValue) -> KaffeeResult<KaffeeValue> {
+    match (op, value) {
+        ("!", v) => Ok(KaffeeValue::Boolean(!is_truthy(&v))),
+        ("-", KaffeeValue::Number(n)) => Ok(KaffeeValue::Number(-n)),
+        ("+", KaffeeValue::Number(n)) => Ok(KaffeeValue::Number(n)),
+        _ => Err(KaffeeError::new(ErrorKind::Type,
+            format!("Invalid operand type for unary operator \"{}\"", op)))
     }
-
-    KaffeeValue::Number(match &op[..] {
-        "+" => l + r,
-        "-" => l - r,
-        "*" => l * r,
-        "/" => l / r,
-        "%" => l % r,
-        "**" => l.powf(r),
-        _ => panic!("Invalid operator for two number types \"{}\"", op)
-    })
 }
 
-// Ops with two bools
-fn bools (l: bool, op: &String, r: bool) -> KaffeeValue {
-    KaffeeValue::Boolean(match &op[..] {
-        "&&" => l && r,
-        "||" => l || r,
-        _ => panic!("Invalid operator for two boolean types \"{}\"", op)
+// Ops with two numbers
+fn maths (l: f64, op: BinaryOp, r: f64) -> KaffeeResult<KaffeeValue> {
+    // No NaN or Infinity in Kaffee
+    if (op == BinaryOp::Divide || op == BinaryOp::Remainder) && r == 0. {
+        return Err(KaffeeError::new(ErrorKind::Arithmetic,
+            String::from("Division by zero")))
+    }
+
+    Ok(match op {
+        BinaryOp::Greater => KaffeeValue::Boolean(l > r),
+        BinaryOp::Less => KaffeeValue::Boolean(l < r),
+        BinaryOp::GreaterEqual => KaffeeValue::Boolean(l >= r),
+        BinaryOp::LessEqual => KaffeeValue::Boolean(l <= r),
+        BinaryOp::Add => KaffeeValue::Number(l + r),
+        BinaryOp::Subtract => KaffeeValue::Number(l - r),
+        BinaryOp::Multiply => KaffeeValue::Number(l * r),
+        BinaryOp::Divide => KaffeeValue::Number(l / r),
+        BinaryOp::Remainder => KaffeeValue::Number(l % r),
+        BinaryOp::Power => KaffeeValue::Number(l.powf(r)),
+        _ => return invalid_operator(op.as_str(), "number")
     })
 }
 
-fn strings (l: String, op: &String, r: String) -> KaffeeValue {
-    KaffeeValue::String(match &op[..] {
-        "+" => format!("{}{}", l, r),
-        _ => panic!("Invalid operator for two string types \"{}\"", op)
-    })
+fn strings (l: &str, op: BinaryOp, r: &str) -> KaffeeResult<KaffeeValue> {
+    Ok(KaffeeValue::String(match op {
+        BinaryOp::Add => [l, r].concat().into(),
+        _ => return invalid_operator(op.as_str(), "string")
+    }))
 }
@@ -0,0 +1,39 @@
+use kaffee::interpretting::arena;
+
+#[test]
+fn freed_slots_are_reused () {
+    let mut a = arena::new();
+    let first = a.insert("first");
+    a.insert("second");
+    a.remove(first);
+
+    let third = a.insert("third");
+    assert_eq!(third.index(), first.index());
+    assert_eq!(a.len(), 2);
+    assert_eq!(a.slot_count(), 2);
+}
+
+#[test]
+fn stale_handles_see_nothing () {
+    let mut a = arena::new();
+    let old = a.insert(1);
+    a.remove(old);
+    let new = a.insert(2);
+
+    assert_ne!(old, new);
+    assert_eq!(a.get(old), None);
+    assert_eq!(a.remove(old), None);
+    assert_eq!(a[new], 2);
+}
+
+#[test]
+fn retain_frees_what_it_drops () {
+    let mut a = arena::new();
+    let handles: Vec<_> = (0..10).map(|n| a.insert(n)).collect();
+    a.retain(|_, n| n % 2 == 0);
+
+    assert_eq!(a.len(), 5);
+    assert!(a.contains(handles[4]));
+    assert!(!a.contains(handles[5]));
+    assert_eq!(a.iter().map(|(_, n)| *n).collect::<Vec<_>>(), vec![0, 2, 4, 6, 8]);
+}
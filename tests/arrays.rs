@@ -0,0 +1,112 @@
+use kaffee::{KaffeeValue, ErrorKind};
+
+mod common;
+use common::*;
+
+#[test]
+fn aliases_see_the_same_items () {
+    let mut interp = run("
+        let a = [1, 2]
+        let b = a
+        push(b, 3)
+        b[0] = 10
+    ");
+    assert_eq!(eval(&mut interp, "stringify(a)"), string("[10, 2, 3]"));
+    assert_eq!(eval(&mut interp, "a == b"), KaffeeValue::Boolean(true));
+    // Different arrays with the same items aren't the same array
+    assert_eq!(eval(&mut interp, "[1] == [1]"), KaffeeValue::Boolean(false));
+}
+
+#[test]
+fn functions_can_change_arrays_they_are_given () {
+    let mut interp = run("
+        fn fill(list, n) {
+            for let i = 0 i < n i++ push(list, i)
+        }
+        let nums = []
+        fill(nums, 3)
+        let obj = { list: nums }
+        push(obj.list, 3)
+    ");
+    assert_eq!(eval(&mut interp, "stringify(nums)"), string("[0, 1, 2, 3]"));
+}
+
+#[test]
+fn push_pop_insert_remove () {
+    let mut interp = run("
+        let a = []
+        let pushed = push(a, \"b\")
+        push(a, \"d\")
+        insert(a, 0, \"a\")
+        insert(a, 2, \"c\")
+        insert(a, len(a), \"e\")
+        let removed = remove(a, 1)
+        let popped = pop(a)
+    ");
+    assert_eq!(eval(&mut interp, "pushed"), KaffeeValue::Number(1.));
+    assert_eq!(eval(&mut interp, "removed"), string("b"));
+    assert_eq!(eval(&mut interp, "popped"), string("e"));
+    assert_eq!(eval(&mut interp, "stringify(a)"), string("[\"a\", \"c\", \"d\"]"));
+}
+
+#[test]
+fn append_changes_the_array_in_place () {
+    let mut interp = run("
+        let a = [1]
+        let b = append(a, 2)
+    ");
+    assert_eq!(eval(&mut interp, "stringify(a)"), string("[1, 2]"));
+    assert_eq!(eval(&mut interp, "a == b"), KaffeeValue::Boolean(true));
+}
+
+#[test]
+fn assigning_one_past_the_end_grows () {
+    let mut interp = run("
+        let a = []
+        for let i = 0 i < 3 i++ a[len(a)] = i * 2
+        a[1]++
+    ");
+    assert_eq!(eval(&mut interp, "stringify(a)"), string("[0, 3, 4]"));
+    assert_eq!(eval(&mut interp, "a[len(a) - 1]"), KaffeeValue::Number(4.));
+
+    let err = run_err("let a = [1] a[5] = 2");
+    assert_eq!(err.kind, ErrorKind::Key);
+    let err = run_err("let a = [1] a[0.5] = 2");
+    assert_eq!(err.kind, ErrorKind::Key);
+}
+
+#[test]
+fn bad_indexes_throw () {
+    assert_eq!(run_err("pop([])").kind, ErrorKind::Key);
+    assert_eq!(run_err("remove([1], 1)").kind, ErrorKind::Key);
+    assert_eq!(run_err("insert([1], 3, 0)").kind, ErrorKind::Key);
+    assert_eq!(run_err("remove([1], -1)").kind, ErrorKind::Key);
+    assert_eq!(run_err("push({}, 1)").kind, ErrorKind::Type);
+}
+
+#[test]
+fn arrays_holding_themselves_can_be_printed () {
+    let mut interp = run("
+        let a = [1]
+        push(a, a)
+        push(a, [a])
+    ");
+    assert_eq!(eval(&mut interp, "stringify(a)"), string("[1, [...], [[...]]]"));
+}
+
+#[test]
+fn items_survive_collections () {
+    let mut interp = run("
+        let a = []
+        for let i = 0 i < 100 i++ {
+            push(a, { n: i })
+            let garbage = [i, i]
+        }
+        pop(a)
+    ");
+    interp.gc_collect();
+    assert_eq!(eval(&mut interp, "len(a)"), KaffeeValue::Number(99.));
+    assert_eq!(eval(&mut interp, "a[98].n"), KaffeeValue::Number(98.));
+    // Just the one array left
+    assert_eq!(interp.vars.arrays.len(), 1);
+}
@@ -0,0 +1,55 @@
+// Helpers for the tests. Each test file only uses some of them.
+#![allow(dead_code)]
+
+use kaffee::{interpreter, Interpreter, KaffeeValue, KaffeeError, Backend};
+use kaffee::std_lib::functions::rust_stringify;
+
+// Runs a script, keeping the interpreter to look at what it left behind
+pub fn run (code: &str) -> Interpreter {
+    let mut interp = interpreter::new(code.to_string()).unwrap();
+    interp.run().unwrap();
+    interp
+}
+
+// The error a script fails with
+pub fn run_err (code: &str) -> KaffeeError {
+    let mut interp = interpreter::new(code.to_string()).unwrap();
+    interp.run().unwrap_err()
+}
+
+pub fn eval (interp: &mut Interpreter, code: &str) -> KaffeeValue {
+    interp.eval(code.to_string()).unwrap()
+}
+
+pub fn string (st: &str) -> KaffeeValue {
+    KaffeeValue::String(st.into())
+}
+
+// What a script gave back on one backend, or the error it failed with.
+// Calls can only go 50 deep, so runaway recursion gives up quickly.
+pub fn outcome (code: &str, backend: Backend) -> String {
+    outcome_with(code, backend, |_| {})
+}
+
+// The same, with the interpreter set up some other way first
+pub fn outcome_with (code: &str, backend: Backend, setup: impl FnOnce(&mut Interpreter)) -> String {
+    let mut interp = interpreter::new(String::new()).unwrap();
+    interp.backend = backend;
+    interp.limits.max_call_depth = Some(50);
+    setup(&mut interp);
+    match interp.eval(code.to_string()) {
+        Ok(val) => rust_stringify(&val, &mut interp.vars),
+        Err(e) => format!("{}: {} {:?}\n{}", e.kind.name(), e.message, e.span, e.render_trace())
+    }
+}
+
+// Runs a script on both backends, expecting the same thing from each
+pub fn same (code: &str) -> String {
+    same_with(code, |_| {})
+}
+
+pub fn same_with (code: &str, setup: impl Fn(&mut Interpreter)) -> String {
+    let walked = outcome_with(code, Backend::TreeWalker, &setup);
+    assert_eq!(walked, outcome_with(code, Backend::Bytecode, &setup), "backends disagree on:\n{}", code);
+    walked
+}
@@ -0,0 +1,256 @@
+use kaffee::{interpreter, Interpreter, KaffeeValue, GcSettings};
+
+mod common;
+use common::*;
+
+// Runs then collects, so only what's reachable is left
+fn collected (code: &str) -> Interpreter {
+    let mut interp = run(code);
+    interp.gc_collect();
+    interp
+}
+
+// Runs without any explicit collections
+fn run_with (code: &str, settings: GcSettings) -> Interpreter {
+    let mut interp = interpreter::new(code.to_string()).unwrap();
+    *interp.gc_settings() = settings;
+    interp.run().unwrap();
+    interp
+}
+
+fn incremental () -> GcSettings {
+    GcSettings {
+        threshold: 16,
+        incremental: true,
+        step_size: 4,
+        ..GcSettings::default()
+    }
+}
+
+#[test]
+fn deeply_nested_objects_survive () {
+    let mut interp = collected("let o = { a: { b: { c: { d: 42 } } } }");
+    assert_eq!(eval(&mut interp, "o.a.b.c.d"), KaffeeValue::Number(42.));
+}
+
+#[test]
+fn deeply_nested_arrays_survive () {
+    let mut interp = collected("let a = [[1, [2, [3, [4]]]]]");
+    assert_eq!(eval(&mut interp, "a[0][1][1][1][0]"), KaffeeValue::Number(4.));
+}
+
+#[test]
+fn mixed_nesting_survives () {
+    let mut interp = collected("
+        let o = { list: [{ name: \"first\", tags: [\"x\", { deep: true }] }] }
+    ");
+    assert_eq!(eval(&mut interp, "o.list[0].name"), KaffeeValue::String("first".into()));
+    assert_eq!(eval(&mut interp, "o.list[0].tags[1].deep"), KaffeeValue::Boolean(true));
+}
+
+#[test]
+fn nested_values_survive_collection_inside_blocks () {
+    // Each block leaves a collection behind it
+    let mut interp = collected("
+        let o = { inner: { n: 0 } }
+        let i = 0
+        while i < 5 {
+            let junk = { more: { junk: [1, 2, 3] } }
+            o.inner.n = o.inner.n + 1
+            i++
+        }
+    ");
+    assert_eq!(eval(&mut interp, "o.inner.n"), KaffeeValue::Number(5.));
+}
+
+#[test]
+fn closures_keep_nested_captures_alive () {
+    let mut interp = collected("
+        fn makeCounter() {
+            let state = { inner: { count: 0 } }
+            return fn() {
+                state.inner.count++
+                return state.inner.count
+            }
+        }
+        const counters = { list: [makeCounter()] }
+    ");
+    eval(&mut interp, "counters.list[0]()");
+    interp.gc_collect();
+    assert_eq!(eval(&mut interp, "counters.list[0]()"), KaffeeValue::Number(2.));
+}
+
+#[test]
+fn recursive_functions_survive () {
+    // The function lives in the scope it captures, which is a cycle
+    let mut interp = collected("
+        fn fact(n) {
+            if n == 0 { return 1 }
+            return n * fact(n - 1)
+        }
+    ");
+    assert_eq!(eval(&mut interp, "fact(5)"), KaffeeValue::Number(120.));
+}
+
+#[test]
+fn unreachable_values_are_freed () {
+    let mut interp = collected("let keep = { a: 1 }");
+    let before = interp.vars.alloced.len();
+    let scopes_before = interp.vars.scopes.len();
+
+    interp.eval(String::from("
+        {
+            let garbage = { nested: { deeper: [1, 2, 3, { x: [4] }] } }
+        }
+    ")).unwrap();
+    interp.gc_collect();
+
+    assert_eq!(interp.vars.alloced.len(), before);
+    assert_eq!(interp.vars.scopes.len(), scopes_before);
+}
+
+#[test]
+fn unreachable_closures_are_freed () {
+    let mut interp = collected("
+        fn make() {
+            let captured = [1, 2, 3]
+            return fn() { return captured }
+        }
+    ");
+    let before = interp.vars.alloced.len();
+
+    interp.eval(String::from("
+        {
+            let f = make()
+            f()
+        }
+    ")).unwrap();
+    interp.gc_collect();
+
+    assert_eq!(interp.vars.alloced.len(), before);
+}
+
+#[test]
+fn returned_values_survive_collection () {
+    let mut interp = collected("
+        fn make(n) { return { a: { b: n } } }
+        let list = [make(1), make(2)]
+        let sum = make(3).a.b + make(4).a.b
+    ");
+    assert_eq!(eval(&mut interp, "list[1].a.b"), KaffeeValue::Number(2.));
+    assert_eq!(eval(&mut interp, "sum"), KaffeeValue::Number(7.));
+}
+
+#[test]
+fn pending_values_survive_finally () {
+    let mut interp = collected("
+        fn make() {
+            try { return { a: [1, 2] } } finally { let junk = { b: [3] } }
+        }
+    ");
+    assert_eq!(eval(&mut interp, "make().a[1]"), KaffeeValue::Number(2.));
+}
+
+#[test]
+fn function_arguments_are_freed () {
+    let mut interp = collected("fn take(x) { let local = [x, x] return 1 }");
+    let before = interp.vars.alloced.len();
+
+    interp.eval(String::from("
+        let i = 0
+        while i < 100 {
+            take({ big: [1, 2, 3] })
+            i++
+        }
+    ")).unwrap();
+    interp.gc_collect();
+
+    // Only i is left
+    assert_eq!(interp.vars.alloced.len(), before + 1);
+}
+
+#[test]
+fn long_loops_collect_as_they_go () {
+    // The loop's scope is never left until the end
+    let interp = run_with("
+        {
+            let i = 0
+            while i < 5000 {
+                let o = { a: [i, i], b: { c: i } }
+                i++
+            }
+        }
+    ", GcSettings { threshold: 200, ..GcSettings::default() });
+
+    assert!(interp.vars.gc.collections > 0);
+    assert!(interp.vars.alloced.len() < 500);
+}
+
+#[test]
+fn recursion_collects_as_it_goes () {
+    let interp = run_with("
+        fn make() { return { a: [1, 2, 3] } }
+        fn rec(n) {
+            if n == 0 { return 0 }
+            make()
+            return rec(n - 1)
+        }
+        rec(100)
+    ", GcSettings { threshold: 200, ..GcSettings::default() });
+
+    assert!(interp.vars.gc.collections > 0);
+    assert!(interp.vars.alloced.len() < 600);
+}
+
+#[test]
+fn collections_wait_for_the_threshold () {
+    let interp = run_with("
+        let i = 0
+        while i < 100 { let o = { a: i } i++ }
+    ", GcSettings { threshold: 1000000, ..GcSettings::default() });
+
+    assert_eq!(interp.vars.gc.collections, 0);
+}
+
+#[test]
+fn incremental_collection_keeps_live_values () {
+    let mut interp = run_with("
+        fn makeCounter() {
+            let state = { inner: { count: 0 } }
+            return fn() {
+                state.inner.count++
+                return state.inner.count
+            }
+        }
+        let counters = [makeCounter(), makeCounter()]
+        let tree = { left: { values: [1, 2] }, right: null }
+        let i = 0
+        while i < 500 {
+            let junk = { a: [i, { b: i }] }
+            counters[0]()
+            tree.right = { values: [i, { deep: i }] }
+            i++
+        }
+    ", incremental());
+
+    assert!(interp.vars.gc.collections > 1);
+    assert_eq!(eval(&mut interp, "counters[0]()"), KaffeeValue::Number(501.));
+    assert_eq!(eval(&mut interp, "counters[1]()"), KaffeeValue::Number(1.));
+    assert_eq!(eval(&mut interp, "tree.left.values[1]"), KaffeeValue::Number(2.));
+    assert_eq!(eval(&mut interp, "tree.right.values[1].deep"), KaffeeValue::Number(499.));
+}
+
+#[test]
+fn incremental_collection_frees_garbage () {
+    let mut interp = run_with("
+        let i = 0
+        while i < 2000 {
+            let junk = { a: [i, i, i] }
+            i++
+        }
+    ", incremental());
+
+    assert!(interp.vars.alloced.len() < 200);
+    interp.gc_collect();
+    assert!(interp.vars.alloced.len() < 20);
+}
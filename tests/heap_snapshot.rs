@@ -0,0 +1,113 @@
+use kaffee::{Interpreter, HeapSnapshot};
+use kaffee::interpretting::variables::GLOBAL_SCOPE;
+use kaffee::interpretting::heap_snapshot::{SnapshotValue, SnapshotRoot};
+
+mod common;
+use common::*;
+
+fn global_id (interp: &Interpreter) -> usize {
+    interp.vars.scopestack[GLOBAL_SCOPE].index()
+}
+
+// The value a global points to
+fn binding<'a> (snap: &'a HeapSnapshot, scope: usize, name: &str) -> &'a SnapshotValue {
+    let scope = snap.scopes.iter().find(|s| s.id == scope).unwrap();
+    let (_, idx) = scope.bindings.iter().find(|(n, _)| n == name).unwrap();
+    snap.value(*idx).unwrap()
+}
+
+#[test]
+fn edges_follow_objects_and_arrays () {
+    let interp = run("let o = { list: [1, 2] }");
+    let snap = interp.heap_snapshot();
+
+    let o = binding(&snap, global_id(&interp), "o");
+    assert_eq!(o.kind, "Object");
+
+    // Objects' keys and values live on their own too,
+    // shared by every copy of the object
+    let (_, entries) = &o.edges[0];
+    let entries = snap.value(*entries).unwrap();
+    assert_eq!(entries.kind, "ObjectEntries");
+    let (_, list) = entries.edges.iter().find(|(name, _)| name == "list").unwrap();
+
+    let list = snap.value(*list).unwrap();
+    assert_eq!(list.kind, "Array");
+    assert_eq!(list.label, "[2 items]");
+
+    let (_, items) = &list.edges[0];
+    let items = snap.value(*items).unwrap();
+    assert_eq!(items.kind, "ArrayItems");
+    let names: Vec<&str> = items.edges.iter().map(|(n, _)| &n[..]).collect();
+    assert_eq!(names, vec!["[0]", "[1]"]);
+}
+
+#[test]
+fn roots_and_garbage () {
+    let interp = run("
+        let kept = [1]
+        let dropped = [2, 3]
+        dropped = null
+    ");
+    let snap = interp.heap_snapshot();
+    let globals = global_id(&interp);
+
+    let kept = binding(&snap, globals, "kept");
+    assert_eq!(kept.root, Some(SnapshotRoot::Scope(globals)));
+
+    // The variable's slot now holds null, but the old array's
+    // items are still there until a collection
+    let garbage: Vec<&str> = snap.unreachable().map(|v| &v.label[..]).collect();
+    assert_eq!(garbage, vec!["2", "3", "[2 items]"]);
+}
+
+#[test]
+fn closures_reach_their_captured_scopes () {
+    let interp = run("
+        fn counter() {
+            let count = 0
+            return fn() { count++ }
+        }
+        let inc = counter()
+    ");
+    let snap = interp.heap_snapshot();
+
+    let inc = binding(&snap, global_id(&interp), "inc");
+    let captured = *inc.captures.last().unwrap();
+    let scope = snap.scopes.iter().find(|s| s.id == captured).unwrap();
+    assert!(!scope.is_root);
+    assert_eq!(scope.depth, None);
+
+    // Only alive because of the closure, but the globals still reach it
+    let count = binding(&snap, captured, "count");
+    assert_eq!(count.root, Some(SnapshotRoot::Scope(global_id(&interp))));
+}
+
+#[test]
+fn temporary_roots_keep_values_alive () {
+    let mut interp = run("");
+    let held = interp.vars.new_array(vec![string("held")]);
+    interp.vars.root(&held);
+    let snap = interp.heap_snapshot();
+
+    // Nothing in scope points at the array, but the GC wouldn't collect it
+    let items = snap.values.iter().find(|v| v.kind == "ArrayItems").unwrap();
+    assert_eq!(items.root, Some(SnapshotRoot::Temporary));
+    assert_eq!(snap.unreachable().count(), 0);
+    assert!(snap.to_json().contains("\"root\": \"temporary\""));
+}
+
+#[test]
+fn exporters_escape_strings () {
+    let interp = run("let s = \"a\\b\"");
+    let snap = interp.heap_snapshot();
+
+    let json = snap.to_json();
+    assert!(json.contains("\"label\": \"\\\"a\\\\b\\\"\""));
+    assert!(json.trim_end().ends_with('}'));
+
+    let dot = snap.to_dot();
+    assert!(dot.starts_with("digraph heap {"));
+    assert!(dot.contains("[label=\"s\"]"));
+    assert!(dot.contains("String: \\\"a\\\\b\\\""));
+}
@@ -0,0 +1,161 @@
+use std::time::{Duration, Instant};
+use kaffee::{interpreter, Interpreter, KaffeeValue, ErrorKind, ExecutionLimits};
+
+fn limited (code: &str, max_values: Option<usize>, max_bytes: Option<usize>) -> Interpreter {
+    let mut interp = interpreter::new(code.to_string()).unwrap();
+    interp.vars.limits.max_values = max_values;
+    interp.vars.limits.max_bytes = max_bytes;
+    interp
+}
+
+#[test]
+fn runaway_append_runs_out_of_memory () {
+    let mut interp = limited("
+        let list = []
+        while true { list = append(list, 1) }
+    ", Some(2000), None);
+
+    let err = interp.run().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::OutOfMemory);
+}
+
+#[test]
+fn byte_limit_catches_big_strings () {
+    let mut interp = limited("
+        let s = \"0123456789\"
+        while true { s = s + s }
+    ", None, Some(1_000_000));
+
+    let err = interp.run().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::OutOfMemory);
+}
+
+#[test]
+fn closures_dont_pay_for_their_body () {
+    let body = "let unused = 1 + 2\n".repeat(500);
+    let mut interp = limited(&format!("
+        fn make () {{ return fn () {{ {} }} }}
+        let kept = []
+        for let i = 0 i < 1000 i++ {{ push(kept, make()) }}
+    ", body), None, Some(1_000_000));
+
+    interp.run().unwrap();
+}
+
+#[test]
+fn garbage_doesnt_count_towards_the_limit () {
+    let mut interp = limited("
+        let i = 0
+        while i < 5000 {
+            let junk = [i, i, i, i]
+            i++
+        }
+    ", Some(500), None);
+
+    interp.run().unwrap();
+}
+
+#[test]
+fn scripts_can_catch_out_of_memory () {
+    let mut interp = limited("
+        let kind = null
+        try {
+            let list = []
+            while true { list = append(list, 1) }
+        } catch e {
+            kind = e.kind
+        }
+    ", Some(1000), None);
+
+    interp.run().unwrap();
+    assert_eq!(interp.vars.get_global("kind"), Some(KaffeeValue::String("OutOfMemoryError".into())));
+    // The list went with the try block
+    assert!(interp.vars.alloced.len() < 1000);
+}
+
+fn new (code: &str) -> Interpreter {
+    interpreter::new(code.to_string()).unwrap()
+}
+
+#[test]
+fn step_budget_stops_infinite_loops () {
+    let mut interp = new("while true {}");
+    interp.limits.max_steps = Some(10000);
+
+    let err = interp.run().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::ExecutionLimit);
+    assert!(interp.steps <= 10001);
+}
+
+#[test]
+fn deadline_stops_infinite_loops () {
+    let mut interp = new("
+        fn spin() { return 1 }
+        while true { spin() }
+    ");
+    interp.limits.deadline = Some(Instant::now() + Duration::from_millis(50));
+
+    let err = interp.run().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::ExecutionLimit);
+}
+
+#[test]
+fn execution_limits_cant_be_caught () {
+    let mut interp = new("
+        let cleaned_up = false
+        while true {
+            try {
+                while true {}
+            } catch e {
+            } finally {
+                cleaned_up = true
+            }
+        }
+    ");
+    interp.limits.max_steps = Some(10000);
+
+    let err = interp.run().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::ExecutionLimit);
+    assert_eq!(interp.vars.get_global("cleaned_up"), Some(KaffeeValue::Boolean(false)));
+}
+
+#[test]
+fn deep_recursion_is_a_catchable_error () {
+    let mut interp = new("
+        fn rec(n) { return rec(n + 1) + 1 }
+        let kind = null
+        try { rec(0) } catch e { kind = e.kind }
+        rec(0)
+    ");
+    interp.limits.max_call_depth = Some(50);
+
+    let err = interp.run().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::StackOverflow);
+    assert_eq!(err.trace.len(), 50);
+    assert_eq!(interp.vars.get_global("kind"), Some(KaffeeValue::String("StackOverflowError".into())));
+    // Everything was unwound
+    assert!(interp.call_stack.is_empty());
+}
+
+#[test]
+fn default_call_depth_fits_a_main_thread () {
+    // The same size of stack as a program's main thread usually gets
+    let runner = std::thread::Builder::new().stack_size(8 * 1024 * 1024).spawn(|| {
+        let mut interp = new("
+            fn rec (n) {
+                while true {
+                    try {
+                        let x = [n, { next: rec(n + 1) + 1 }]
+                        return x[1].next
+                    } finally { }
+                }
+            }
+            rec(0)
+        ");
+        let err = interp.run().unwrap_err();
+        (err.kind, err.trace.len())
+    }).unwrap();
+
+    let depth = ExecutionLimits::default().max_call_depth.unwrap();
+    assert_eq!(runner.join().unwrap(), (ErrorKind::StackOverflow, depth));
+}
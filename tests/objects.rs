@@ -0,0 +1,203 @@
+use kaffee::KaffeeValue;
+
+mod common;
+use common::*;
+
+fn boolean (bl: bool) -> KaffeeValue {
+    KaffeeValue::Boolean(bl)
+}
+
+#[test]
+fn new_keys_show_up_through_every_alias () {
+    let mut interp = run("
+        let a = { x: 1 }
+        let b = a
+        b.y = 2
+        a.x = 10
+    ");
+    assert_eq!(eval(&mut interp, "stringify(a)"), string("{ x: 10, y: 2 }"));
+    assert_eq!(eval(&mut interp, "stringify(b)"), string("{ x: 10, y: 2 }"));
+    assert_eq!(eval(&mut interp, "a == b"), boolean(true));
+    assert_eq!(eval(&mut interp, "a == { x: 10, y: 2 }"), boolean(false));
+}
+
+#[test]
+fn functions_can_change_objects_they_are_given () {
+    let mut interp = run("
+        fn birthday(person) { person.age++ person.party = true }
+        let adam = { age: 17 }
+        birthday(adam)
+        let people = [adam]
+        people[0].name = \"Adam\"
+    ");
+    assert_eq!(eval(&mut interp, "stringify(adam)"), string("{ age: 18, party: true, name: \"Adam\" }"));
+}
+
+#[test]
+fn clone_copies_one_level () {
+    let mut interp = run("
+        let a = { inner: { n: 1 }, list: [1] }
+        let b = clone(a)
+        b.extra = true
+        b.inner.n = 2
+        push(b.list, 2)
+
+        let arr = [{ n: 1 }]
+        let arr2 = clone(arr)
+        push(arr2, 2)
+        arr2[0].n = 3
+    ");
+    assert_eq!(eval(&mut interp, "a == b"), boolean(false));
+    // New keys only go on the copy, but what's inside is shared
+    assert_eq!(eval(&mut interp, "stringify(a)"), string("{ inner: { n: 2 }, list: [1, 2] }"));
+    assert_eq!(eval(&mut interp, "stringify(arr)"), string("[{ n: 3 }]"));
+    assert_eq!(eval(&mut interp, "len(arr2)"), KaffeeValue::Number(2.));
+    // Anything else is just given back
+    assert_eq!(eval(&mut interp, "clone(\"s\")"), string("s"));
+}
+
+#[test]
+fn deep_clone_copies_everything () {
+    let mut interp = run("
+        let a = { inner: { n: 1 }, list: [[1]] }
+        let b = deep_clone(a)
+        b.inner.n = 2
+        push(b.list[0], 2)
+    ");
+    assert_eq!(eval(&mut interp, "stringify(a)"), string("{ inner: { n: 1 }, list: [[1]] }"));
+    assert_eq!(eval(&mut interp, "stringify(b)"), string("{ inner: { n: 2 }, list: [[1, 2]] }"));
+}
+
+#[test]
+fn deep_clone_keeps_the_shape_of_cycles () {
+    let mut interp = run("
+        let shared = { n: 1 }
+        let a = { left: shared, right: shared }
+        a.me = a
+        let b = deep_clone(a)
+    ");
+    assert_eq!(eval(&mut interp, "b.me == b"), boolean(true));
+    assert_eq!(eval(&mut interp, "b.left == b.right"), boolean(true));
+    assert_eq!(eval(&mut interp, "b.left == a.left"), boolean(false));
+}
+
+#[test]
+fn cycles_can_be_printed () {
+    let mut interp = run("
+        let a = { n: 1 }
+        a.me = a
+        a.list = [a]
+        let shared = [1]
+        let b = { x: shared, y: shared }
+    ");
+    assert_eq!(eval(&mut interp, "stringify(a)"), string("{ n: 1, me: {...}, list: [{...}] }"));
+    // Seeing the same thing twice isn't a cycle
+    assert_eq!(eval(&mut interp, "stringify(b)"), string("{ x: [1], y: [1] }"));
+}
+
+#[test]
+fn thrown_objects_are_caught_as_themselves () {
+    let mut interp = run("
+        let err = { message: \"bad\" }
+        let caught = null
+        try { throw err } catch e { caught = e }
+    ");
+    assert_eq!(eval(&mut interp, "caught == err"), boolean(true));
+    assert_eq!(eval(&mut interp, "err.message"), string("bad"));
+}
+
+#[test]
+fn objects_survive_collections () {
+    let mut interp = run("
+        let keep = {}
+        for let i = 0 i < 100 i++ {
+            keep[i] = { n: i }
+            let garbage = { n: i }
+        }
+        let copy = deep_clone(keep)
+    ");
+    interp.gc_collect();
+    assert_eq!(eval(&mut interp, "keep[99].n"), KaffeeValue::Number(99.));
+    assert_eq!(eval(&mut interp, "copy[42].n"), KaffeeValue::Number(42.));
+    // keep, copy and the 200 objects inside them
+    assert_eq!(interp.vars.objects.len(), 202);
+}
+
+#[test]
+fn keys_stay_in_the_order_they_were_added () {
+    let mut interp = run("
+        let o = { z: 1, a: 2, z: 3 }
+        o.m = 4
+        o.a = 5
+
+        let big = {}
+        for let i = 0 i < 1000 i++ big[i] = i
+    ");
+    // A key given twice keeps its first place and its last value
+    assert_eq!(eval(&mut interp, "stringify(o)"), string("{ z: 3, a: 5, m: 4 }"));
+    assert_eq!(eval(&mut interp, "big[999] + big[0]"), KaffeeValue::Number(999.));
+}
+
+#[test]
+fn keys_match_like_equality () {
+    let mut interp = run("
+        let list = [1]
+        let o = {}
+        o[0] = \"zero\"
+        o[\"0\"] = \"string zero\"
+        o[list] = \"list\"
+        o[true] = \"yes\"
+        o[null] = \"nothing\"
+    ");
+    assert_eq!(eval(&mut interp, "o[-0]"), string("zero"));
+    assert_eq!(eval(&mut interp, "o[\"0\"]"), string("string zero"));
+    // Arrays and objects are keys by identity
+    assert_eq!(eval(&mut interp, "o[list]"), string("list"));
+    assert!(interp.eval(String::from("o[[1]]")).is_err());
+    assert_eq!(eval(&mut interp, "o[1 == 1]"), string("yes"));
+    assert_eq!(eval(&mut interp, "o[null]"), string("nothing"));
+
+    let err = interp.eval(String::from("o[println] = 1")).unwrap_err();
+    assert_eq!(err.kind, kaffee::ErrorKind::Type);
+}
+
+#[test]
+fn keys_keep_arrays_and_objects_alive () {
+    let mut interp = run("
+        let o = {}
+        o[[1, 2]] = \"array key\"
+        o[{ n: 1 }] = \"object key\"
+    ");
+    interp.gc_collect();
+    assert_eq!(eval(&mut interp, "stringify(o)"), string("{ [1, 2]: \"array key\", { n: 1 }: \"object key\" }"));
+}
+
+#[test]
+fn deep_clone_handles_deep_nesting () {
+    let mut interp = run("
+        let a = []
+        for let i = 0 i < 100000 i++ { a = [a, { i: i }] }
+        let b = deep_clone(a)
+        let depth = 0
+        while len(b) > 0 {
+            b = b[0]
+            depth++
+        }
+    ");
+    assert_eq!(eval(&mut interp, "depth"), KaffeeValue::Number(100000.));
+}
+
+#[test]
+fn printing_handles_deep_nesting () {
+    let mut interp = run("
+        let a = []
+        for let i = 0 i < 300000 i++ { a = [a] }
+        let s = stringify(a)
+        let cyclic = [{ n: 1 }]
+        push(cyclic, cyclic)
+        cyclic[0].self = cyclic
+    ");
+    assert_eq!(eval(&mut interp, "len(s)"), KaffeeValue::Number(600002.));
+    assert_eq!(eval(&mut interp, "stringify([[1, \"a\"], {}])"), string("[[1, \"a\"], {}]"));
+    assert_eq!(eval(&mut interp, "stringify(cyclic)"), string("[{ n: 1, self: [...] }, [...]]"));
+}
@@ -0,0 +1,154 @@
+use kaffee::interpreter;
+use kaffee::parsing::ast_utils::{ASTNode, NodeKind};
+
+mod common;
+use common::*;
+
+// The same on both backends, optimized or not
+fn run (code: &str) -> String {
+    let plain = same_with(code, |interp| interp.optimize = false);
+    assert_eq!(plain, same(code), "optimizing changes:\n{}", code);
+    plain
+}
+
+fn parse (code: &str, optimize: bool) -> Vec<ASTNode> {
+    let mut interp = interpreter::new(String::new()).unwrap();
+    interp.optimize = optimize;
+    interp.parse(code.to_string()).unwrap()
+}
+
+fn declared_value (node: &ASTNode) -> &NodeKind {
+    match &node.kind {
+        NodeKind::Declaration(dcl) => &dcl.assignment.right.kind,
+        _ => unreachable!()
+    }
+}
+
+fn function_body (node: &ASTNode) -> &[ASTNode] {
+    match &node.kind {
+        NodeKind::FunctionDefinition(fd) => &fd.body,
+        _ => unreachable!()
+    }
+}
+
+#[test]
+fn folds_constant_expressions () {
+    let ast = parse("
+        let day = 60 * 60 * 24
+        let greeting = \"a\" + \"b\"
+        let negative = -(2 ** 3)
+        let both = !false && 1 < 2
+        let either = null || \"default\"
+        let left = 1 / 0
+        let mixed = 1 + day
+    ", true);
+
+    assert_eq!(declared_value(&ast[0]), &NodeKind::Number(86400.));
+    assert_eq!(declared_value(&ast[1]), &NodeKind::String("ab".into()));
+    assert_eq!(declared_value(&ast[2]), &NodeKind::Number(-8.));
+    assert_eq!(declared_value(&ast[3]), &NodeKind::Boolean(true));
+    assert_eq!(declared_value(&ast[4]), &NodeKind::String("default".into()));
+    // Errors are left for when they're reached
+    assert!(matches!(declared_value(&ast[5]), NodeKind::BinaryNode(_)));
+    assert!(matches!(declared_value(&ast[6]), NodeKind::BinaryNode(_)));
+}
+
+#[test]
+fn drops_code_that_cant_run () {
+    let ast = parse("
+        fn f (x) {
+            if false { println(x) }
+            while null { x++ }
+            if 1 > 2 { x = 1 } else { x = 2 }
+            return x
+            println(\"never\")
+        }
+        fn g (x) {
+            if x { return 1 } else { throw 2 }
+            x++
+        }
+    ", true);
+
+    let body = function_body(&ast[0]);
+    assert_eq!(body.len(), 2);
+    // The else block is all that's left of the if
+    assert!(matches!(&body[0].kind, NodeKind::BlockStatement(bs) if bs.len() == 1));
+    assert!(matches!(&body[1].kind, NodeKind::ReturnStatement(_)));
+    assert_eq!(function_body(&ast[1]).len(), 1);
+}
+
+#[test]
+fn can_be_turned_off () {
+    let ast = parse("
+        let day = 60 * 60 * 24
+        fn f () { return 1 println(2) }
+    ", false);
+    assert!(matches!(declared_value(&ast[0]), NodeKind::BinaryNode(_)));
+    assert_eq!(function_body(&ast[1]).len(), 2);
+}
+
+#[test]
+fn results_are_the_same () {
+    assert_eq!(run("
+        let log = []
+        fn note (x) { push(log, x) return x }
+        const limit = 10 * 10
+        fn f (n) {
+            if true && n < limit { note(\"small\") } else { note(\"big\") }
+            if false note(\"never\")
+            while false { note(\"never\") }
+            if null || n { note(n) }
+            return n + 1 * 2
+            note(\"after\")
+        }
+        let results = [f(1), f(200), \"x\" + \"y\" == \"xy\", -1 - -1, !null]
+        push(results, log)
+        results
+    "), "[3, 202, true, 0, true, [\"small\", 1, \"big\", 200]]");
+
+    // Loops that leave early
+    assert_eq!(run("
+        let seen = []
+        let i = 0
+        while i < 5 {
+            if i == 3 { break push(seen, \"never\") }
+            push(seen, i)
+            i++
+            continue
+            push(seen, \"never\")
+        }
+        seen
+    "), "[0, 1, 2]");
+
+    // Declarations in branches that are taken or dropped
+    assert_eq!(run("
+        let x = 1
+        fn f () {
+            if true let y = 2
+            if false { let x = 3 }
+            return x + y
+        }
+        f()
+    "), "3");
+
+    // Top-level statements still give the same value
+    assert_eq!(run("5 if false 1"), "null");
+    assert_eq!(run("if true len([1])"), "null");
+    assert_eq!(run("if false 1 else len([1])"), "null");
+    assert_eq!(run("if 1 + 1 > 3 1 else { len([1]) }"), "null");
+    assert_eq!(run("1 + 2 * 3"), "7");
+}
+
+#[test]
+fn errors_are_the_same () {
+    // Failing operations aren't folded away
+    run("let x = 1 / 0");
+    run("fn f () { return \"a\" - 1 } f()");
+    run("fn f () { if true return -\"a\" } f()");
+    run("let x = [1, 2] x[1 + 1]");
+    run("fn f () { throw 1 + 1 return 5 } f()");
+
+    // Names in dead code still have to exist
+    assert!(run("fn f () { return 1 missing() }").starts_with("ReferenceError"));
+    assert!(run("if false { missing }").starts_with("ReferenceError"));
+}
@@ -0,0 +1,25 @@
+use kaffee::interpretting::ordered_map;
+
+#[test]
+fn keeps_insertion_order () {
+    let mut m = ordered_map::new();
+    for key in ["c", "a", "b"] {
+        m.insert(key, key.len());
+    }
+
+    let keys: Vec<&str> = m.iter().map(|(k, _)| *k).collect();
+    assert_eq!(keys, vec!["c", "a", "b"]);
+    assert_eq!(m.get(&"a"), Some(&1));
+    assert_eq!(m.get(&"d"), None);
+}
+
+#[test]
+fn overwriting_keeps_the_place () {
+    let mut m = ordered_map::new();
+    m.insert("x", 1);
+    m.insert("y", 2);
+
+    assert_eq!(m.insert("x", 3), Some(1));
+    assert_eq!(m.len(), 2);
+    assert_eq!(m.values().copied().collect::<Vec<_>>(), vec![3, 2]);
+}
@@ -0,0 +1,148 @@
+use kaffee::{interpreter, KaffeeValue, ErrorKind, Backend};
+use kaffee::parsing::ast_utils::{ASTNode, NodeKind, Binding};
+
+mod common;
+use common::*;
+
+#[test]
+fn identifiers_get_depths_and_slots () {
+    let mut interp = interpreter::new(String::new()).unwrap();
+    let ast = interp.parse(String::from("
+        let a = 1
+        fn f (x, y) {
+            let z = y
+            return a
+        }
+    ")).unwrap();
+
+    let body = match &ast[1].kind {
+        NodeKind::FunctionDefinition(fd) => &fd.body,
+        _ => unreachable!()
+    };
+    let binding = |node: &ASTNode| match &node.kind {
+        NodeKind::Declaration(dcl) => match &dcl.assignment.right.kind {
+            NodeKind::Identifier(id) => id.binding,
+            _ => unreachable!()
+        },
+        NodeKind::ReturnStatement(rs) => match &rs.kind {
+            NodeKind::Identifier(id) => id.binding,
+            _ => unreachable!()
+        },
+        _ => unreachable!()
+    };
+
+    // y is the second argument, one scope out from the body
+    assert_eq!(binding(&body[0]), Some(Binding { depth: 1, slot: 1 }));
+    // a is the first global, past the body and arguments
+    assert_eq!(binding(&body[1]), Some(Binding { depth: 2, slot: 0 }));
+}
+
+#[test]
+fn unresolved_identifiers_fail_before_running () {
+    let mut interp = interpreter::new(String::from("
+        let ran = true
+        fn f () { return missing }
+    ")).unwrap();
+
+    let err = interp.run().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Reference);
+    assert_eq!(err.message, "Unresolved identifier \"missing\"");
+    assert_eq!(err.span.unwrap().start.line, 3);
+    assert_eq!(interp.vars.get_global("ran"), None);
+}
+
+#[test]
+fn unresolved_identifiers_cant_be_caught () {
+    for backend in [Backend::TreeWalker, Backend::Bytecode] {
+        let mut interp = interpreter::new(String::from("
+            let caught = false
+            try { missing } catch e { caught = true }
+        ")).unwrap();
+        interp.backend = backend;
+
+        // Reported before the try gets a chance to run
+        let err = interp.run().unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Reference);
+        assert_eq!(err.span.unwrap().start.line, 3);
+        assert_eq!(interp.vars.get_global("caught"), None);
+    }
+}
+
+#[test]
+fn hosts_can_add_globals_before_running () {
+    let mut interp = interpreter::new(String::from("let x = limit * 2")).unwrap();
+    interp.vars.set_global("limit", KaffeeValue::Number(21.));
+    interp.run().unwrap();
+    assert_eq!(interp.vars.get_global("x"), Some(KaffeeValue::Number(42.)));
+}
+
+#[test]
+fn functions_see_names_declared_after_them () {
+    assert_eq!(same("
+        fn isEven (n) { if n == 0 return true return isOdd(n - 1) }
+        fn isOdd (n) { if n == 0 return false return isEven(n - 1) }
+        isEven(10)
+    "), "true");
+}
+
+#[test]
+fn reads_before_a_declaration_see_further_out () {
+    assert_eq!(same("
+        let x = 1
+        let seen = []
+        {
+            push(seen, x)
+            let x = 2
+            push(seen, x)
+        }
+        push(seen, x)
+        seen
+    "), "[1, 2, 1]");
+
+    // A local that shadows a std_lib function
+    assert_eq!(same("
+        let lengths = []
+        {
+            push(lengths, len([1, 2]))
+            let len = fn (a) { return 0 }
+            push(lengths, len([1, 2]))
+        }
+        lengths
+    "), "[2, 0]");
+
+    assert_eq!(same("
+        let x = 1
+        fn f () {
+            let x = x + 1
+            return x
+        }
+        f()
+    "), "2");
+}
+
+#[test]
+fn conditional_declarations () {
+    assert_eq!(same("
+        fn f (c) {
+            if c let x = 1
+            return x
+        }
+        let x = \"global\"
+        let results = [f(true), f(false)]
+        results
+    "), "[1, \"global\"]");
+    assert!(same("let a = 1 let a = 2").starts_with("RedeclarationError: Attempt to shadow identifier \"a\" within the same scope!
+(You declared a variable with a conflicting name)"));
+}
+
+#[test]
+fn globals_keep_their_slots_between_evals () {
+    let mut interp = interpreter::new(String::new()).unwrap();
+    interp.eval(String::from("let count = 0 fn inc () { count++ }")).unwrap();
+    interp.eval(String::from("inc() inc()")).unwrap();
+    interp.eval(String::from("let other = 5")).unwrap();
+    assert_eq!(interp.eval(String::from("count + other")).unwrap(), KaffeeValue::Number(7.));
+
+    let err = interp.eval(String::from("let count = 1")).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Redeclaration);
+}
@@ -0,0 +1,181 @@
+use kaffee::{interpreter, KaffeeValue};
+use kaffee::parsing::ast_utils::{ASTNode, NodeKind};
+
+mod common;
+use common::*;
+
+// Collecting often, to catch anything a tail call forgets to root
+fn run (code: &str) -> String {
+    same_with(code, |interp| interp.gc_settings().threshold = 100)
+}
+
+#[test]
+fn tail_recursion_doesnt_get_deeper () {
+    assert_eq!(run("
+        fn sum (n, total) {
+            if n == 0 return total
+            return sum(n - 1, total + n)
+        }
+        sum(100000, 0)
+    "), "5000050000");
+
+    assert_eq!(run("
+        fn isEven (n) { if n == 0 return true return isOdd(n - 1) }
+        fn isOdd (n) { if n == 0 return false return isEven(n - 1) }
+        let results = [isEven(20001), isOdd(20001)]
+        results
+    "), "[false, true]");
+
+    // Anywhere a return leaves the function straight away
+    assert_eq!(run("
+        fn countdown (n) {
+            while true {
+                if n == 0 return \"done\"
+                { return countdown(n - 1) }
+            }
+        }
+        fn retry (n) {
+            if n == 0 return \"gave up\"
+            try { throw n } catch { return retry(n - 1) }
+        }
+        let results = [countdown(10000), retry(10000)]
+        results
+    "), "[\"done\", \"gave up\"]");
+}
+
+#[test]
+fn other_calls_still_get_deeper () {
+    let overflow = "StackOverflowError: Maximum call depth of 50 exceeded";
+    assert!(run("
+        fn down (n) { if n == 0 return 0 return down(n - 1) + 1 }
+        down(100)
+    ").starts_with(overflow));
+
+    // The finally block has to be there when the call returns
+    assert!(run("
+        fn guarded (n) {
+            if n == 0 return 0
+            try { return guarded(n - 1) } finally { }
+        }
+        guarded(100)
+    ").starts_with(overflow));
+    // But not when it's the finally block making the call
+    assert_eq!(run("
+        fn cleaned (n) {
+            if n == 0 return 0
+            try { } finally { return cleaned(n - 1) }
+        }
+        cleaned(100)
+    "), "0");
+}
+
+#[test]
+fn tail_calls_keep_their_meaning () {
+    assert_eq!(run("
+        let log = []
+        fn fail () { throw \"oops\" }
+        fn caught () {
+            try { return fail() } catch e { push(log, e) }
+            return \"after\"
+        }
+        fn finished () {
+            try { return len(log) } finally { push(log, \"finally\") }
+        }
+        let results = [caught(), finished(), log]
+        results
+    "), "[\"after\", 1, [\"oops\", \"finally\"]]");
+
+    // Closures, native functions and things that can't be called
+    assert_eq!(run("
+        fn adder (n) { return fn (x) { return x + n } }
+        fn apply (n) { return adder(n)(1) }
+        fn size (list) { return len(list) }
+        let results = [apply(2), size([1, 2, 3])]
+        results
+    "), "[3, 3]");
+    assert!(run("fn f () { return 1() } f()").starts_with("TypeError"));
+    assert!(run("fn g (a) { return a } fn f () { return g() } f()")
+        .starts_with("ArgumentError: Function takes 1 arguments, but 0 were supplied"));
+}
+
+#[test]
+fn tail_calls_replace_the_caller_in_traces () {
+    let trace = run("
+        fn inner () { return 1 / 0 }
+        fn middle () { return inner() }
+        fn outer () { return middle() + 1 }
+        outer()
+    ");
+    assert!(trace.contains("at inner"), "{}", trace);
+    assert!(!trace.contains("at middle"), "{}", trace);
+    assert!(trace.contains("at outer"), "{}", trace);
+}
+
+#[test]
+fn arguments_survive_collections_between_calls () {
+    assert_eq!(run("
+        fn build (n, list) {
+            if n == 0 return list
+            return build(n - 1, { n: n, rest: list, garbage: [n, [n]] })
+        }
+        fn total (list, sum) {
+            if list == null return sum
+            return total(list.rest, sum + list.n)
+        }
+        total(build(2000, null), 0)
+    "), "2001000");
+}
+
+#[test]
+fn resolver_marks_tail_calls () {
+    let mut interp = interpreter::new(String::new()).unwrap();
+    // Everything after the first return would be dropped
+    interp.optimize = false;
+    let ast = interp.parse(String::from("
+        fn f () {
+            return g()
+            return g() + 1
+            try { return g() } finally { }
+        }
+        fn g () { return 1 }
+        return g()
+    ")).unwrap();
+
+    let tail = |node: &ASTNode| match &node.kind {
+        NodeKind::ReturnStatement(rs) => match &rs.kind {
+            NodeKind::FunctionCall(cp) => Some(cp.tail),
+            _ => None
+        },
+        NodeKind::TryStatement(tp) => match &tp.body.kind {
+            NodeKind::BlockStatement(bs) => match &bs[0].kind {
+                NodeKind::ReturnStatement(rs) => match &rs.kind {
+                    NodeKind::FunctionCall(cp) => Some(cp.tail),
+                    _ => None
+                },
+                _ => None
+            },
+            _ => None
+        },
+        _ => None
+    };
+    let body = match &ast[0].kind {
+        NodeKind::FunctionDefinition(fd) => &fd.body,
+        _ => unreachable!()
+    };
+
+    assert_eq!(tail(&body[0]), Some(true));
+    assert_eq!(tail(&body[1]), None);
+    assert_eq!(tail(&body[2]), Some(false));
+    // Not in a function at all
+    assert_eq!(tail(&ast[2]), Some(false));
+}
+
+#[test]
+fn host_calls_with_default_limits () {
+    let mut interp = interpreter::new(String::from("
+        fn loop (n) { if n == 0 return \"done\" return loop(n - 1) }
+        let result = loop(100000)
+    ")).unwrap();
+    interp.run().unwrap();
+    assert_eq!(interp.vars.get_global("result"), Some(KaffeeValue::String("done".into())));
+}
@@ -0,0 +1,324 @@
+use kaffee::{interpreter, Backend, ErrorKind};
+use kaffee::std_lib::functions::rust_stringify;
+
+mod common;
+use common::*;
+
+#[test]
+fn arithmetic_and_logic () {
+    assert_eq!(same("1 + 2 * 3 - 4 / 2 % 3 ** 2"), "5");
+    assert_eq!(same("\"a\" + \"b\" == \"ab\" && !false"), "true");
+    assert_eq!(same("null || 0"), "0");
+    assert_eq!(same("false && 1 / 0"), "false");
+    assert_eq!(same("-(+3)"), "-3");
+    same("1 / 0");
+    same("1 + \"a\"");
+    same("-\"a\"");
+}
+
+#[test]
+fn variables_and_scopes () {
+    assert_eq!(same("
+        let x = 1
+        const y = 2
+        {
+            let x = 10
+            x++
+        }
+        x += y
+        let result = [x, x++, ++x, x--, --x]
+        result
+    "), "[3, 3, 5, 5, 3]");
+    same("const c = 1 c = 2");
+    same("let a = 1 let a = 2");
+    same("missing + 1");
+    same("let c = 1 c()");
+}
+
+#[test]
+fn loops () {
+    assert_eq!(same("
+        let total = 0
+        for let i = 0 i < 10 i++ {
+            if i == 7 { break }
+            if i % 2 == 0 { i++ continue }
+            total += i
+        }
+        total
+    "), "9");
+
+    assert_eq!(same("
+        let seen = []
+        let i = 0
+        while i < 3 {
+            let j = 0
+            while true {
+                j++
+                if j > i { break }
+                push(seen, [i, j])
+            }
+            i++
+        }
+        seen
+    "), "[[1, 1], [2, 1], [2, 2]]");
+}
+
+#[test]
+fn functions_and_closures () {
+    assert_eq!(same("
+        fn fib(n) {
+            if n < 2 { return n }
+            return fib(n - 1) + fib(n - 2)
+        }
+        fib(15)
+    "), "610");
+
+    assert_eq!(same("
+        fn counter() {
+            let count = 0
+            return fn() { return ++count }
+        }
+        let a = counter()
+        let b = counter()
+        a() a()
+        let result = [a(), b()]
+        result
+    "), "[3, 1]");
+
+    // Falling off the end, or breaking out of no loop, gives null
+    assert_eq!(same("
+        fn nothing() { let x = 1 }
+        fn breaks() { break return 1 }
+        let result = [nothing(), breaks()]
+        result
+    "), "[null, null]");
+
+    same("fn f(a) {} f()");
+    same("fn f(a, a) {} f(1, 2)");
+    same("fn f() { return g() } fn g() { return h } f()");
+}
+
+#[test]
+fn arrays_and_objects () {
+    assert_eq!(same("
+        let a = [1, 2]
+        a[len(a)] = 3
+        a[0] += 10
+        let o = { x: 1, y: [a] }
+        o.z = o.x++
+        o[a] = \"array key\"
+        o.y[0][1]--
+        let result = [a, o.x, o.z, o[a]]
+        result
+    "), "[[11, 1, 3], 2, 1, \"array key\"]");
+
+    // The object and key only get resolved once, even for a new key
+    assert_eq!(same("
+        let calls = 0
+        fn key() { calls++ return calls }
+        let o = {}
+        o[key()] = 1
+        calls
+    "), "1");
+
+    same("let o = {} o.missing");
+    same("let o = {} o.missing++");
+    same("let a = [1] a[5] = 1");
+    same("let n = 1 n.x");
+    same("let o = {} o[println] = 1");
+}
+
+#[test]
+fn try_catch_finally () {
+    assert_eq!(same("
+        let log = []
+        fn risky(n) {
+            try {
+                if n > 1 { throw { message: \"too big\", n: n } }
+                push(log, \"ok\")
+                return n
+            } catch (e) {
+                push(log, e.message)
+                return -1
+            } finally {
+                push(log, \"finally\")
+            }
+        }
+        let result = [risky(1), risky(2), log]
+        result
+    "), "[1, -1, [\"ok\", \"finally\", \"too big\", \"finally\"]]");
+
+    // finally runs for break, continue and return, and
+    // leaving it early replaces what was going to happen
+    assert_eq!(same("
+        let log = []
+        for let i = 0 i < 3 i++ {
+            try {
+                if i == 0 { i++ continue }
+                break
+            } finally {
+                push(log, i)
+            }
+        }
+        fn overrides() {
+            try { return 1 } finally { return 2 }
+        }
+        fn swallows() {
+            while true {
+                try { throw \"x\" } finally { break }
+            }
+            return \"swallowed\"
+        }
+        let result = [log, overrides(), swallows()]
+        result
+    "), "[[1, 1], 2, \"swallowed\"]");
+
+    // Errors from the interpreter itself get caught as objects,
+    // with the stack from where they happened
+    let caught = same("
+        fn inner() { return 1 / 0 }
+        fn outer() { return inner() + 1 }
+        let caught = null
+        try { outer() } catch (e) { caught = e }
+        let result = [caught.kind, caught.message, caught.stack]
+        result
+    ");
+    assert!(caught.starts_with("[\"ArithmeticError\", \"Division by zero\", \"  at inner"));
+    assert!(caught.contains("at outer"));
+}
+
+#[test]
+fn uncaught_errors_match () {
+    same("
+        fn inner() { throw \"oops\" }
+        fn outer() {
+            try { inner() } finally { let x = 1 }
+        }
+        outer()
+    ");
+    same("
+        try { throw \"a\" } catch (e) { throw e + \"b\" }
+    ");
+    same("
+        try { throw \"a\" } catch { 1 + null } finally { println }
+    ");
+
+    let anonymous = same("
+        let f = fn () { throw \"oops\" }
+        f()
+    ");
+    assert!(anonymous.contains("at <anonymous>"), "{}", anonymous);
+}
+
+#[test]
+fn vm_calls_dont_use_the_rust_stack () {
+    let mut interp = interpreter::new(String::from("
+        fn down(n) {
+            if n == 0 { return 0 }
+            return down(n - 1) + 1
+        }
+        let depth = down(100000)
+    ")).unwrap();
+    interp.backend = Backend::Bytecode;
+    interp.limits.max_call_depth = None;
+    interp.run().unwrap();
+
+    assert_eq!(interp.eval(String::from("depth")).unwrap(), kaffee::KaffeeValue::Number(100000.));
+}
+
+#[test]
+fn vm_limits () {
+    let mut interp = interpreter::new(String::from("while true {}")).unwrap();
+    interp.backend = Backend::Bytecode;
+    interp.limits.max_steps = Some(10000);
+    let err = interp.run().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::ExecutionLimit);
+
+    let code = "
+        fn forever() { return forever() + 1 }
+        let caught = null
+        try { forever() } catch (e) { caught = e.kind }
+        caught
+    ";
+    assert_eq!(same(code), "StackOverflowError");
+}
+
+#[test]
+fn functions_carry_over_between_backends () {
+    let mut interp = interpreter::new(String::new()).unwrap();
+    interp.eval(String::from("
+        fn adder (n) { return fn (x) { return x + n } }
+        let add1 = adder(1)
+        let add10 = adder(10)
+    ")).unwrap();
+
+    // The closures share a body, and get compiled the first time they're called
+    interp.backend = Backend::Bytecode;
+    let total = interp.eval(String::from("
+        let total = 0
+        for let i = 0 i < 100 i++ { total = add1(total) + add10(0) }
+        total
+    ")).unwrap();
+    assert_eq!(total, kaffee::KaffeeValue::Number(1100.));
+}
+
+// How far a script got on a step budget: its outcome, the steps it
+// counted and what it logged on the way
+fn budgeted (code: &str, backend: Backend, steps: u64) -> String {
+    let mut interp = interpreter::new(String::new()).unwrap();
+    interp.backend = backend;
+    interp.eval(String::from("let log = []")).unwrap();
+    interp.steps = 0;
+    interp.limits.max_steps = Some(steps);
+    let res = match interp.eval(code.to_string()) {
+        Ok(val) => rust_stringify(&val, &mut interp.vars),
+        Err(e) => format!("{}: {} {:?}", e.kind.name(), e.message, e.span)
+    };
+    let log = interp.vars.get_global("log").unwrap();
+    format!("{} after {} steps, log {}", res, interp.steps, rust_stringify(&log, &mut interp.vars))
+}
+
+#[test]
+fn step_budgets_stop_in_the_same_place () {
+    let code = "
+        fn note (x) { push(log, x) return x }
+        fn count (n) { if n == 0 return 0 return count(n - 1) }
+        let i = 0
+        while i < 3 {
+            { note(i) }
+            if i == 1 note(\"one\") else { note(count(2)) }
+            i++
+        }
+        try { note(\"try\") throw 1 } catch e { note(e) } finally { note(\"finally\") }
+        for let j = 0 j < 2 j++ { note([j, { j: j }]) }
+        note(count(3)) + 1
+    ";
+    let full = budgeted(code, Backend::TreeWalker, u64::MAX);
+    for steps in 0..200 {
+        let walked = budgeted(code, Backend::TreeWalker, steps);
+        assert_eq!(walked, budgeted(code, Backend::Bytecode, steps), "with {} steps", steps);
+        if walked == full {
+            return
+        }
+    }
+    panic!("never finished: {}", full);
+}
+
+#[test]
+fn vm_collects_garbage () {
+    let mut interp = interpreter::new(String::from("
+        let kept = []
+        for let i = 0 i < 2000 i++ {
+            let garbage = [i, [i], { i: i }]
+            if i % 100 == 0 { push(kept, garbage) }
+        }
+    ")).unwrap();
+    interp.backend = Backend::Bytecode;
+    interp.gc_settings().threshold = 100;
+    interp.run().unwrap();
+    interp.gc_collect();
+
+    assert!(interp.vars.gc.collections > 1);
+    assert_eq!(interp.eval(String::from("kept[19][2].i")).unwrap(), kaffee::KaffeeValue::Number(1900.));
+    assert!(interp.vars.heap_len() < 500);
+}